@@ -0,0 +1,112 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
+use serde::{Deserialize, Serialize};
+use crate::parallel_double_phase_collision_check;
+use crate::shape::shape::ShapeTrait;
+
+// why a pair is skipped by self-collision checks
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AllowedReason {
+    Always, // collided in every sample, e.g. adjacent links overlapping at the joint
+    Never,  // never collided in any sample, out of reach of each other
+    User,   // set by hand
+}
+
+// per-pair allowed collision matrix over n shapes (e.g. the links of one robot model).
+// allowed pairs are not checked. only the upper triangle is stored, (i, j) and (j, i) are the same entry.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AllowedCollisionMatrix {
+    n: usize,
+    allowed: Vec<Option<AllowedReason>>,
+}
+
+impl AllowedCollisionMatrix {
+    // nothing allowed, every pair is checked
+    pub fn new(n: usize) -> Self {
+        Self { n, allowed: vec![None; n * n.saturating_sub(1) / 2] }
+    }
+
+    // samples num_samples configurations, a pair colliding in all of them is allowed as Always,
+    // one colliding in none as Never. sample_poses must return one pose per shape.
+    pub fn from_sampling<S, F>(shapes: &[S], mut sample_poses: F, num_samples: usize, cut_off: usize) -> Self
+    where
+        S: ShapeTrait + Sync,
+        F: FnMut() -> Vec<LieGroupISE3q>,
+    {
+        let mut acm = Self::new(shapes.len());
+        if num_samples == 0 {
+            return acm;
+        }
+        let mut counts = vec![0usize; acm.allowed.len()];
+        for _ in 0..num_samples {
+            let poses = sample_poses();
+            assert_eq!(poses.len(), shapes.len(), "sample_poses must return one pose per shape");
+            for contact in parallel_double_phase_collision_check(shapes, &poses, cut_off) {
+                counts[acm.slot(contact.i, contact.j)] += 1;
+            }
+        }
+        for (entry, &count) in acm.allowed.iter_mut().zip(counts.iter()) {
+            if count == num_samples {
+                *entry = Some(AllowedReason::Always);
+            } else if count == 0 {
+                *entry = Some(AllowedReason::Never);
+            }
+        }
+        acm
+    }
+
+    pub fn len(&self) -> usize {
+        self.n
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.n == 0
+    }
+
+    // index of (i, j) in the packed upper triangle, row i holds n - 1 - i entries
+    fn slot(&self, i: usize, j: usize) -> usize {
+        let (i, j) = if i < j { (i, j) } else { (j, i) };
+        assert!(i != j && j < self.n, "invalid pair ({}, {}) for {} shapes", i, j, self.n);
+        i * (2 * self.n - i - 1) / 2 + (j - i - 1)
+    }
+
+    pub fn allow(&mut self, i: usize, j: usize, reason: AllowedReason) {
+        let s = self.slot(i, j);
+        self.allowed[s] = Some(reason);
+    }
+
+    pub fn disallow(&mut self, i: usize, j: usize) {
+        let s = self.slot(i, j);
+        self.allowed[s] = None;
+    }
+
+    pub fn reason(&self, i: usize, j: usize) -> Option<AllowedReason> {
+        self.allowed[self.slot(i, j)]
+    }
+
+    pub fn is_allowed(&self, i: usize, j: usize) -> bool {
+        self.reason(i, j).is_some()
+    }
+
+    // whether (i, j) still has to be tested, usable as a PairFilter predicate:
+    // PairFilter::new(&filters).with_predicate(&|i, j| acm.checks(i, j))
+    pub fn checks(&self, i: usize, j: usize) -> bool {
+        !self.is_allowed(i, j)
+    }
+
+    pub fn save_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(writer, self).map_err(io::Error::from)
+    }
+
+    pub fn load_json<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        let acm: Self = serde_json::from_reader(reader).map_err(io::Error::from)?;
+        if acm.allowed.len() != acm.n * acm.n.saturating_sub(1) / 2 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "allowed collision matrix has the wrong number of entries"));
+        }
+        Ok(acm)
+    }
+}
@@ -1,12 +1,9 @@
 use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
 use apollo_rust_spatial::vectors::V3;
-use rand::Rng;
-use parallel_collision_detection::{shape, bvh, gjk, my_hulls_to_parry_hulls, serial_parry_gjk, parallel_parry_gjk};
-use parallel_collision_detection::gjk::gjk::{parallel_narrow_phase_check, serial_narrow_phase_check};
-use shape::shape::ConvexPolyhedron as ConvexHull;
+use parallel_collision_detection::{my_hulls_to_parry_hulls, serial_parry_gjk, parallel_parry_gjk, serial_parry_contact, parallel_parry_contact};
+use parallel_collision_detection::gjk::gjk::{parallel_narrow_phase_check, serial_narrow_phase_check, parallel_narrow_phase_check_with, serial_narrow_phase_check_with, QueryOptions};
 use parallel_collision_detection::parallel_double_phase_collision_check;
 use parallel_collision_detection::serial_double_phase_collision_check;
-use parallel_collision_detection::shape::shape::ShapeTrait;
 use parallel_collision_detection::generate_random_hulls;
 use std::time::Instant;
 use std::fs::File;
@@ -19,7 +16,7 @@ fn main() ->std::io::Result<()> {
         for n in [10, 20, 50, 100, 200, 500, 1000, 2000, 3000, 4000, 5000, 6000, 7000, 8000, 9000, 10000] {
             writeln!(file, "n={}", n)?;
             println!("cut_off={}, n={}, running...",cut_off, n);
-            let mut hulls = generate_random_hulls(n, (50, 100), (V3::new(0.0, 0.0, 0.0), V3::new(1.0, 1.0, 1.0)));
+            let hulls = generate_random_hulls(n, (50, 100), (V3::new(0.0, 0.0, 0.0), V3::new(1.0, 1.0, 1.0)));
             //let mut hull2 = generate_random_hulls(n/2, (50, 100), (V3::new(0.0, 0.0, 0.0), V3::new(1.0, 1.0, 1.0)));
             //hulls.append(&mut hull2);
             let parry_hulls = my_hulls_to_parry_hulls(&hulls);
@@ -47,6 +44,24 @@ fn main() ->std::io::Result<()> {
             parallel_narrow_phase_check(&indices, &hulls, &poses);
             writeln!(file, "parallel_our_narrow={:?}", t.elapsed())?;
 
+            // the same narrow phases with penetration depth, against parry's contact query
+            let penetration = QueryOptions::default().with_penetration();
+            let t = Instant::now();
+            serial_parry_contact(&indices, &parry_hulls, &poses);
+            writeln!(file, "serial_parry_contact={:?}", t.elapsed())?;
+
+            let t = Instant::now();
+            serial_narrow_phase_check_with(&indices, &hulls, &poses, &penetration);
+            writeln!(file, "serial_our_contact={:?}", t.elapsed())?;
+
+            let t = Instant::now();
+            parallel_parry_contact(&indices, &parry_hulls, &poses);
+            writeln!(file, "parallel_parry_contact={:?}", t.elapsed())?;
+
+            let t = Instant::now();
+            parallel_narrow_phase_check_with(&indices, &hulls, &poses, &penetration);
+            writeln!(file, "parallel_our_contact={:?}", t.elapsed())?;
+
             let t = Instant::now();
             serial_double_phase_collision_check(&hulls, &poses, 4);
             writeln!(file, "serial_double={:?}", t.elapsed())?;
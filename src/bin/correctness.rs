@@ -1,8 +1,38 @@
 use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
 use apollo_rust_spatial::vectors::V3;
-use parallel_collision_detection::{serial_parry_gjk, serial_double_phase_collision_check, parallel_double_phase_collision_check, generate_random_hulls, my_hulls_to_parry_hulls, parallel_parry_gjk};
-use parallel_collision_detection::gjk::gjk::{Contact, serial_narrow_phase_check, parallel_narrow_phase_check};
+use parallel_collision_detection::{serial_double_phase_collision_check, parallel_double_phase_collision_check, generate_random_hulls, my_hulls_to_parry_hulls, parallel_parry_gjk};
+use parallel_collision_detection::{serial_flat_double_phase_collision_check, parallel_flat_double_phase_collision_check};
+use parallel_collision_detection::{parallel_double_phase_proximity_check, new_incremental_bvh, parallel_incremental_collision_check};
+use parallel_collision_detection::{parallel_filtered_collision_check, serial_filtered_collision_check, parallel_filtered_any_collision, serial_filtered_any_collision};
+use parallel_collision_detection::{parallel_bipartite_collision_check, serial_bipartite_collision_check};
+use parallel_collision_detection::world::{CollisionWorld, ShapeHandle};
+use parallel_collision_detection::acm::{AllowedCollisionMatrix, AllowedReason};
+use parallel_collision_detection::filter::{CollisionFilter, PairFilter};
+use parallel_collision_detection::{serial_swept_collision_check, parallel_swept_collision_check, serial_any_collision, parallel_any_collision};
+use parallel_collision_detection::gjk::ccd::{interpolate_pose, swept_aabb, time_of_impact};
+use parallel_collision_detection::gjk::gjk::{Contact, parallel_narrow_phase_check, parallel_narrow_phase_distance};
+use parallel_collision_detection::gjk::gjk::{GJKCache, QueryOptions, parallel_narrow_phase_distance_with, serial_narrow_phase_check_cached_with, parallel_narrow_phase_check_cached_with};
 use parallel_collision_detection::shape::shape::ShapeTrait;
+use parallel_collision_detection::shape::shape::{ConvexPolyhedron as ConvexHull, Shape};
+use parallel_collision_detection::shape::primitives::{Capsule, Cone, Cuboid, Cylinder, Sphere};
+use parallel_collision_detection::shape::compound::Compound;
+use parallel_collision_detection::bvh::structs::{AABB, BuildStrategy};
+use parallel_collision_detection::bvh::srl_bvh::serial_build_bvh_with;
+use parallel_collision_detection::bvh::par_bvh::{parallel_build_bvh, parallel_build_bvh_with, parallel_broad_phase_check, parallel_self_broad_phase_check, parallel_self_broad_phase_any};
+use parallel_collision_detection::bvh::flat::{FlatBVH, serial_build_flat_bvh, parallel_build_flat_bvh, serial_build_flat_bvh_with, parallel_build_flat_bvh_with, serial_flat_broad_phase_check, parallel_flat_broad_phase_check,
+                                              serial_flat_self_broad_phase_check, parallel_flat_self_broad_phase_check, serial_flat_broad_phase_any,
+                                              parallel_flat_broad_phase_any, serial_flat_self_broad_phase_any, parallel_flat_self_broad_phase_any};
+use parallel_collision_detection::bvh::lbvh::parallel_build_lbvh;
+use parry3d_f64::math::{Isometry, Point as ParryPoint, Vector};
+use parry3d_f64::query::{contact as parry_contact, distance as parry_distance};
+use parry3d_f64::shape::{Ball, Capsule as ParryCapsule, Compound as ParryCompound, Cone as ParryCone, ConvexPolyhedron as ParryConvexHull,
+                         Cuboid as ParryCuboid, Cylinder as ParryCylinder, SharedShape};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use rayon::prelude::*;
+
+const DISTANCE_TOL: f64 = 1e-6;
 
 fn check(ground_truth: &[Contact], res: &[Contact], name: &str){
     println!("Checking {}",name);
@@ -21,8 +51,626 @@ fn check(ground_truth: &[Contact], res: &[Contact], name: &str){
     }
     println!("{} passed",name);
 }
+
+fn check_pairs(ground_truth: &[(usize, usize)], res: &[(usize, usize)], name: &str) {
+    println!("Checking {}", name);
+    let mut c1 = ground_truth.to_vec();
+    let mut c2 = res.to_vec();
+    c1.sort();
+    c2.sort();
+    if let Some(p) = (0..c1.len().max(c2.len())).find(|&p| c1.get(p) != c2.get(p)) {
+        panic!("{}-th pair mismatch! ground_truth: {:?}, res: {:?} ({} vs {} pairs)", p, c1.get(p), c2.get(p), c1.len(), c2.len());
+    }
+    println!("{} passed, {} pairs", name, c1.len());
+}
+
+fn check_bool(expected: bool, res: bool, name: &str) {
+    println!("Checking {}", name);
+    if expected != res {
+        panic!("{} mismatch! expected: {}, res: {}", name, expected, res);
+    }
+    println!("{} passed", name);
+}
+
+fn all_pairs(n: usize) -> Vec<(usize, usize)> {
+    (0..n).flat_map(|i| (i + 1..n).map(move |j| (i, j))).collect()
+}
+
+// n random boxes in the unit cube, with sides up to size
+fn random_aabbs(n: usize, size: f64) -> Vec<AABB> {
+    let mut rng = rand::thread_rng();
+    (0..n).map(|_| {
+        let min = V3::new(rng.r#gen(), rng.r#gen(), rng.r#gen());
+        AABB::new(min, min + V3::new(rng.gen_range(0.0..size), rng.gen_range(0.0..size), rng.gen_range(0.0..size)))
+    }).collect()
+}
+
+// every overlapping pair of boxes, by brute force
+fn overlapping_pairs(aabbs: &[AABB]) -> Vec<(usize, usize)> {
+    all_pairs(aabbs.len()).into_par_iter().filter(|&(i, j)| aabbs[i].intersects(&aabbs[j])).collect()
+}
+
+// the LBVH must give the same broad phase pairs as the top-down BVH, also when every
+// morton code is the same
+fn check_lbvh(n: usize) {
+    let aabbs = random_aabbs(n, 0.03);
+    let mut indices: Vec<usize> = (0..n).collect();
+    let bvh = parallel_build_bvh(&mut indices, &aabbs, 4);
+    let ground_truth = parallel_broad_phase_check(&*bvh, &*bvh);
+    check_pairs(&overlapping_pairs(&aabbs), &ground_truth, "top-down broad phase");
+    let mut indices: Vec<usize> = (0..n).collect();
+    let lbvh = parallel_build_lbvh(&mut indices, &aabbs, 4);
+    check_pairs(&ground_truth, &parallel_broad_phase_check(&*lbvh, &*lbvh), "lbvh broad phase");
+    check_pairs(&ground_truth, &parallel_self_broad_phase_check(&*lbvh), "lbvh self broad phase");
+
+    // boxes of different sizes around the same center
+    let mut rng = rand::thread_rng();
+    let center = V3::new(0.5, 0.5, 0.5);
+    let aabbs: Vec<AABB> = (0..n / 10).map(|_| { let e = V3::repeat(rng.gen_range(0.01..0.1)); AABB::new(center - e, center + e) }).collect();
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = parallel_build_bvh(&mut indices, &aabbs, 4);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let lbvh = parallel_build_lbvh(&mut indices, &aabbs, 4);
+    check_pairs(&parallel_broad_phase_check(&*bvh, &*bvh), &parallel_self_broad_phase_check(&*lbvh), "lbvh with equal morton codes");
+}
+
+// lower bound on the distance between two shapes: the gap between them along the normal of their contact.
+// the normal of a compound only separates its nearest part, so a compound is bounded part by part.
+fn distance_lower_bound<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2: &LieGroupISE3q) -> f64 {
+    if let Some(c) = shape1.as_compound() {
+        return c.parts().iter().enumerate().map(|(k, part)| distance_lower_bound(part, &c.part_pose(k, pose1), shape2, pose2)).fold(f64::INFINITY, f64::min);
+    }
+    if let Some(c) = shape2.as_compound() {
+        return c.parts().iter().enumerate().map(|(k, part)| distance_lower_bound(shape1, pose1, part, &c.part_pose(k, pose2))).fold(f64::INFINITY, f64::min);
+    }
+    let n = Contact::new(0, 1, shape1, pose1, shape2, pose2).normal;
+    shape2.support(&(-n), pose2).dot(&n) - shape1.support(&n, pose1).dot(&n)
+}
+
+// compares our distance of every pair against parry's, penetrating pairs must be at distance 0 for parry.
+// parry stops early on curved shapes and may then report more than we do: our distance is accepted if
+// it converged and is confirmed by the lower bound above.
+fn check_distances<S: ShapeTrait>(ours: &[Contact], parry: &[f64], shapes: &[S], poses: &[LieGroupISE3q], name: &str) {
+    println!("Checking {}", name);
+    for (c, &d) in ours.iter().zip(parry) {
+        let distance = c.distance.max(0.0);
+        let certified = || c.converged && distance < d && distance - distance_lower_bound(&shapes[c.i], &poses[c.i], &shapes[c.j], &poses[c.j]) <= DISTANCE_TOL;
+        if (distance - d).abs() > DISTANCE_TOL && !certified() {
+            panic!("pair ({}, {}) mismatch! ours: {} (converged {}), parry: {}", c.i, c.j, c.distance, c.converged, d);
+        }
+    }
+    println!("{} passed, {} pairs", name, ours.len());
+}
+
+fn parry_hull(points: &[V3]) -> ParryConvexHull {
+    let pts: Vec<_> = points.iter().map(|p| ParryPoint::new(p.x, p.y, p.z)).collect();
+    ParryConvexHull::from_convex_hull(&pts).unwrap()
+}
+
+// plates with all their points in one plane, and point sets made of coincident points. parry cannot
+// build a flat hull, so it gets the plate extruded by 1e-9 instead.
+fn check_degenerate_hulls(n: usize) {
+    let mut rng = rand::thread_rng();
+    let mut hulls = Vec::new();
+    let mut parry_hulls = Vec::new();
+    for k in 0..n {
+        let mut points: Vec<V3> = (0..rng.gen_range(4..12))
+            .map(|_| V3::new(rng.gen_range(0.0..0.3), rng.gen_range(0.0..0.3), rng.gen_range(0.0..0.3))).collect();
+        if k % 2 == 0 {
+            points.iter_mut().for_each(|p| p.z = 0.0);
+            let extruded: Vec<V3> = points.iter().flat_map(|p| [*p, p + V3::new(0.0, 0.0, 1e-9)]).collect();
+            parry_hulls.push(parry_hull(&extruded));
+        } else {
+            parry_hulls.push(parry_hull(&points));
+            points = points.iter().flat_map(|p| [*p, *p, *p, p.add_scalar(1e-13)]).collect();
+        }
+        hulls.push(ConvexHull::from_points(&points));
+    }
+    let poses: Vec<_> = (0..n).map(|_| LieGroupISE3q::new_random()).collect();
+    let pairs = all_pairs(n);
+    let ours = parallel_narrow_phase_distance(&pairs, &hulls, &poses);
+    let parry: Vec<f64> = pairs.par_iter()
+        .map(|&(i, j)| parry_distance(&poses[i].0, &parry_hulls[i], &poses[j].0, &parry_hulls[j]).unwrap())
+        .collect();
+    check_distances(&ours, &parry, &hulls, &poses, "flat and degenerate hulls");
+}
+
+// penetration depth and normal from EPA against parry's. where the two disagree, ours has to be a
+// valid minimal translation: moving shape j by depth along our normal makes the shapes just touch,
+// and parry's translation is either deeper or does not separate them.
+fn check_penetration(n: usize) {
+    let mut rng = rand::thread_rng();
+    let hulls = generate_random_hulls(2 * n, (10, 20), (V3::new(0.0, 0.0, 0.0), V3::new(0.3, 0.3, 0.3)));
+    let parry_hulls = my_hulls_to_parry_hulls(&hulls);
+    let options = QueryOptions::default().with_penetration();
+    let (mut penetrating, mut agree) = (0, 0);
+    for k in 0..n {
+        let (a, b) = (2 * k, 2 * k + 1);
+        let pa = LieGroupISE3q::new_random();
+        let mut pb = LieGroupISE3q::new_random();
+        pb.0.translation.vector = pa.0.translation.vector + V3::new(rng.gen_range(-0.15..0.15), rng.gen_range(-0.15..0.15), rng.gen_range(-0.15..0.15));
+        let c = Contact::with_options(a, b, &hulls[a], &pa, &hulls[b], &pb, &options);
+        let pc = parry_contact(&pa.0, &parry_hulls[a], &pb.0, &parry_hulls[b], 0.0).unwrap();
+        let parry_depth = pc.map_or(0.0, |pc| -pc.dist);
+        if c.distance >= -DISTANCE_TOL && parry_depth <= DISTANCE_TOL {
+            continue;
+        }
+        let Some(pc) = pc else { panic!("pair {} penetrates by {} for us but not for parry", k, -c.distance) };
+        penetrating += 1;
+        let parry_normal = V3::new(pc.normal1.x, pc.normal1.y, pc.normal1.z);
+        if (c.distance - pc.dist).abs() <= DISTANCE_TOL && c.normal.dot(&parry_normal) >= 1.0 - DISTANCE_TOL {
+            agree += 1;
+            continue;
+        }
+        let moved = |normal: &V3, depth: f64| {
+            let mut q = pb;
+            q.0.translation.vector += normal * depth;
+            Contact::with_options(a, b, &hulls[a], &pa, &hulls[b], &q, &options).distance
+        };
+        let ours_touch = moved(&c.normal, -c.distance).abs() <= DISTANCE_TOL;
+        let parry_worse = -c.distance <= parry_depth + DISTANCE_TOL || moved(&parry_normal, parry_depth) < -DISTANCE_TOL;
+        if !(ours_touch && parry_worse) {
+            panic!("pair {} mismatch! ours: depth {} normal {:?}, parry: depth {} normal {:?}", k, -c.distance, c.normal, parry_depth, parry_normal);
+        }
+    }
+    println!("penetration passed, {} penetrating pairs, {} identical to parry", penetrating, agree);
+}
+
+// a scene of every primitive and of compounds of hulls, with their parry equivalents. parry's capsule,
+// cylinder and cone have their axis along y where ours is along z, their shapes are turned by a quarter turn around x.
+fn primitives_and_compounds(n: usize) -> (Vec<Shape>, Vec<(SharedShape, Isometry<f64>)>) {
+    let mut rng = rand::thread_rng();
+    let y_to_z = Isometry::rotation(Vector::x() * std::f64::consts::FRAC_PI_2);
+    let mut shapes: Vec<Shape> = Vec::new();
+    let mut parry_shapes: Vec<(SharedShape, Isometry<f64>)> = Vec::new();
+    for k in 0..n {
+        let (h, r) = (rng.gen_range(0.02..0.1), rng.gen_range(0.02..0.1));
+        match k % 6 {
+            0 => { shapes.push(Sphere::new(r).into()); parry_shapes.push((SharedShape::new(Ball::new(r)), Isometry::identity())); }
+            1 => {
+                let e = V3::new(h, r, rng.gen_range(0.02..0.1));
+                shapes.push(Cuboid::new(e).into());
+                parry_shapes.push((SharedShape::new(ParryCuboid::new(Vector::new(e.x, e.y, e.z))), Isometry::identity()));
+            }
+            2 => { shapes.push(Capsule::new(h, r).into()); parry_shapes.push((SharedShape::new(ParryCapsule::new_y(h, r)), y_to_z)); }
+            3 => { shapes.push(Cylinder::new(h, r).into()); parry_shapes.push((SharedShape::new(ParryCylinder::new(h, r)), y_to_z)); }
+            4 => { shapes.push(Cone::new(h, r).into()); parry_shapes.push((SharedShape::new(ParryCone::new(h, r)), y_to_z)); }
+            _ => {
+                let parts = generate_random_hulls(3, (8, 16), (V3::new(0.0, 0.0, 0.0), V3::new(0.1, 0.1, 0.1)));
+                let offsets: Vec<_> = (0..3).map(|_| {
+                    let mut o = LieGroupISE3q::new_random();
+                    o.0.translation.vector *= 0.2;
+                    o
+                }).collect();
+                let parry_parts = my_hulls_to_parry_hulls(&parts).into_iter().zip(&offsets)
+                    .map(|(hull, offset)| (offset.0, SharedShape::new(hull))).collect();
+                shapes.push(Compound::new(parts, offsets).into());
+                parry_shapes.push((SharedShape::new(ParryCompound::new(parry_parts)), Isometry::identity()));
+            }
+        }
+    }
+    (shapes, parry_shapes)
+}
+
+// distances and colliding pairs of such a scene, against parry
+fn check_primitives_and_compounds(n: usize) {
+    let (shapes, parry_shapes) = primitives_and_compounds(n);
+    let poses: Vec<_> = (0..n).map(|_| LieGroupISE3q::new_random()).collect();
+    let pairs = all_pairs(n);
+    let ours = parallel_narrow_phase_distance(&pairs, &shapes, &poses);
+    let parry: Vec<f64> = pairs.par_iter()
+        .map(|&(i, j)| {
+            let ((s1, o1), (s2, o2)) = (&parry_shapes[i], &parry_shapes[j]);
+            parry_distance(&(poses[i].0 * o1), &**s1, &(poses[j].0 * o2), &**s2).unwrap()
+        })
+        .collect();
+    check_distances(&ours, &parry, &shapes, &poses, "primitives and compounds");
+    // a pair GJK could not settle counts as colliding, but the broad phase may drop it: leave those out on both sides
+    let undecided: HashSet<(usize, usize)> = ours.iter().filter(|c| !c.converged).map(|c| (c.i, c.j)).collect();
+    let colliding: Vec<Contact> = ours.into_iter().filter(|c| c.converged && c.distance <= 0.0).collect();
+    let res: Vec<Contact> = parallel_double_phase_collision_check(&shapes, &poses, 4).into_iter()
+        .filter(|c| !undecided.contains(&(c.i, c.j))).collect();
+    check(&colliding, &res, "primitives and compounds double");
+}
+
+// flat trees, built serially and in parallel, must traverse to the same pairs as the pointer BVH
+fn check_flat_bvh(n: usize) {
+    let aabbs = random_aabbs(n, 0.03);
+    let mut indices: Vec<usize> = (0..n).collect();
+    let bvh = parallel_build_bvh(&mut indices, &aabbs, 4);
+    let ground_truth = parallel_broad_phase_check(&*bvh, &*bvh);
+    let mut indices: Vec<usize> = (0..n).collect();
+    let serial = serial_build_flat_bvh(&mut indices, &aabbs, 4);
+    let mut indices: Vec<usize> = (0..n).collect();
+    let parallel = parallel_build_flat_bvh(&mut indices, &aabbs, 4);
+    for (tree, build) in [(&serial, "serial"), (&parallel, "parallel")] {
+        check_pairs(&ground_truth, &serial_flat_self_broad_phase_check(tree), &format!("{} flat, serial self traversal", build));
+        check_pairs(&ground_truth, &parallel_flat_self_broad_phase_check(tree), &format!("{} flat, parallel self traversal", build));
+        check_pairs(&ground_truth, &serial_flat_broad_phase_check(tree, tree), &format!("{} flat, serial traversal", build));
+        check_pairs(&ground_truth, &parallel_flat_broad_phase_check(tree, tree), &format!("{} flat, parallel traversal", build));
+    }
+    check_pairs(&ground_truth, &serial_flat_broad_phase_check(&serial, &parallel), "serial flat against parallel flat");
+    check_pairs(&ground_truth, &parallel_flat_broad_phase_check(&parallel, &serial), "parallel flat against serial flat");
+    check_pairs(&ground_truth, &parallel_flat_self_broad_phase_check(&FlatBVH::from_node(&*bvh)), "flattened pointer BVH");
+
+    // an early-exit test accepting a single pair finds it exactly when the broad phase reports it
+    let overlapping = ground_truth[ground_truth.len() / 2];
+    let apart = all_pairs(n).into_iter().find(|&(i, j)| !aabbs[i].intersects(&aabbs[j])).unwrap();
+    for (target, expected) in [(overlapping, true), (apart, false)] {
+        let test = |i: usize, j: usize| (i, j) == target;
+        check_bool(expected, parallel_self_broad_phase_any(&*bvh, &test), &format!("pointer any {:?}", target));
+        for (tree, build) in [(&serial, "serial"), (&parallel, "parallel")] {
+            check_bool(expected, serial_flat_self_broad_phase_any(tree, &test), &format!("{} flat, serial self any {:?}", build, target));
+            check_bool(expected, parallel_flat_self_broad_phase_any(tree, &test), &format!("{} flat, parallel self any {:?}", build, target));
+            check_bool(expected, serial_flat_broad_phase_any(tree, tree, &test), &format!("{} flat, serial any {:?}", build, target));
+            check_bool(expected, parallel_flat_broad_phase_any(tree, tree, &test), &format!("{} flat, parallel any {:?}", build, target));
+        }
+    }
+}
+
+// trees split with the surface area heuristic must give the same pairs as the midpoint split, also
+// when every center coincides and no split exists
+fn check_sah(n: usize) {
+    let mut rng = rand::thread_rng();
+    let center = V3::new(0.5, 0.5, 0.5);
+    // more boxes than the parallel builds split serially, so that their parallel split sees them too
+    let coincident: Vec<AABB> = (0..5000).map(|_| { let e = V3::repeat(rng.gen_range(0.01..0.1)); AABB::new(center - e, center + e) }).collect();
+    for (aabbs, scene) in [(random_aabbs(n, 0.03), "random"), (coincident, "coincident centers")] {
+        let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+        let bvh = parallel_build_bvh(&mut indices, &aabbs, 4);
+        let ground_truth = parallel_broad_phase_check(&*bvh, &*bvh);
+        let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+        let serial = serial_build_bvh_with(&mut indices, &aabbs, 4, BuildStrategy::Sah);
+        check_pairs(&ground_truth, &parallel_self_broad_phase_check(&*serial), &format!("{}, serial sah", scene));
+        let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+        let parallel = parallel_build_bvh_with(&mut indices, &aabbs, 4, BuildStrategy::Sah);
+        check_pairs(&ground_truth, &parallel_self_broad_phase_check(&*parallel), &format!("{}, parallel sah", scene));
+        let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+        let serial_flat = serial_build_flat_bvh_with(&mut indices, &aabbs, 4, BuildStrategy::Sah);
+        check_pairs(&ground_truth, &parallel_flat_self_broad_phase_check(&serial_flat), &format!("{}, serial flat sah", scene));
+        let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+        let parallel_flat = parallel_build_flat_bvh_with(&mut indices, &aabbs, 4, BuildStrategy::Sah);
+        check_pairs(&ground_truth, &parallel_flat_self_broad_phase_check(&parallel_flat), &format!("{}, parallel flat sah", scene));
+    }
+}
+
+// every shape moves by up to 0.3 along each axis and turns to a random orientation
+fn random_motions(n: usize) -> (Vec<LieGroupISE3q>, Vec<LieGroupISE3q>) {
+    let mut rng = rand::thread_rng();
+    let starts: Vec<_> = (0..n).map(|_| LieGroupISE3q::new_random()).collect();
+    let ends = starts.iter().map(|start| {
+        let step = V3::new(rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3), rng.gen_range(-0.3..0.3));
+        LieGroupISE3q::new(Isometry::from_parts((start.0.translation.vector + step).into(), LieGroupISE3q::new_random().0.rotation))
+    }).collect();
+    (starts, ends)
+}
+
+// pairs that touch at one of the times k / samples of the motion, with the first of these times
+fn sampled_contacts<S: ShapeTrait + Sync>(shapes: &[S], starts: &[LieGroupISE3q], ends: &[LieGroupISE3q], samples: usize) -> Vec<((usize, usize), f64)> {
+    all_pairs(shapes.len()).into_par_iter().filter_map(|(i, j)| {
+        (0..=samples).map(|k| k as f64 / samples as f64).find(|&t| {
+            let c = Contact::new(i, j, &shapes[i], &interpolate_pose(&starts[i], &ends[i], t), &shapes[j], &interpolate_pose(&starts[j], &ends[j], t));
+            c.converged && c.distance <= 0.0
+        }).map(|t| ((i, j), t))
+    }).collect()
+}
+
+// conservative advancement must stop no later than the first sampled contact, and the swept boxes
+// must hold the shape at every sample
+fn check_time_of_impact(n: usize, samples: usize) {
+    let (shapes, _) = primitives_and_compounds(n);
+    let (starts, ends) = random_motions(n);
+    let contacts = sampled_contacts(&shapes, &starts, &ends, samples);
+    println!("Checking time of impact");
+    for &((i, j), t) in &contacts {
+        match time_of_impact(&shapes[i], &starts[i], &ends[i], &shapes[j], &starts[j], &ends[j]) {
+            Some(toi) if toi <= t + 1e-9 => {}
+            toi => panic!("pair ({}, {}) mismatch! touches at t = {}, time of impact: {:?}", i, j, t, toi),
+        }
+    }
+    println!("time of impact passed, {} pairs", contacts.len());
+
+    println!("Checking swept boxes");
+    let swept: Vec<AABB> = (0..n).map(|k| { let (min, max) = swept_aabb(&shapes[k], &starts[k], &ends[k]); AABB::new(min, max) }).collect();
+    for k in 0..n {
+        for t in (0..=samples).map(|s| s as f64 / samples as f64) {
+            let (min, max) = shapes[k].aabb(&interpolate_pose(&starts[k], &ends[k], t));
+            if (0..3).any(|a| min[a] < swept[k].min_coords[a] - 1e-12 || max[a] > swept[k].max_coords[a] + 1e-12) {
+                panic!("shape {} leaves its swept box at t = {}", k, t);
+            }
+        }
+    }
+    let overlapping: HashSet<(usize, usize)> = overlapping_pairs(&swept).into_iter().collect();
+    if let Some(((i, j), t)) = contacts.iter().find(|(pair, _)| !overlapping.contains(pair)) {
+        panic!("pair ({}, {}) touches at t = {} but its swept boxes do not overlap", i, j, t);
+    }
+    println!("swept boxes passed, {} pairs", overlapping.len());
+}
+
+// the swept pipelines must report every pair touching at a sample, no later than that sample
+fn check_swept_pipelines(n: usize, samples: usize) {
+    let (shapes, _) = primitives_and_compounds(n);
+    let (starts, ends) = random_motions(n);
+    let contacts = sampled_contacts(&shapes, &starts, &ends, samples);
+    for (res, name) in [(serial_swept_collision_check(&shapes, &starts, &ends, 4), "serial swept"),
+                        (parallel_swept_collision_check(&shapes, &starts, &ends, 4), "parallel swept")] {
+        println!("Checking {}", name);
+        let tois: HashMap<(usize, usize), f64> = res.iter().map(|r| ((r.i, r.j), r.toi)).collect();
+        for &((i, j), t) in &contacts {
+            match tois.get(&(i, j)) {
+                Some(&toi) if toi <= t + 1e-9 => {}
+                toi => panic!("pair ({}, {}) mismatch! touches at t = {}, reported time of impact: {:?}", i, j, t, toi),
+            }
+        }
+        println!("{} passed, {} of {} pairs touch at a sample", name, contacts.len(), res.len());
+    }
+}
+
+// the early-exit query must agree with whether the full pipeline finds any contact
+fn check_any_collision<S: ShapeTrait + Sync>(shapes: &[S], poses: &[LieGroupISE3q], name: &str) {
+    let expected = !parallel_double_phase_collision_check(shapes, poses, 4).is_empty();
+    check_bool(expected, serial_any_collision(shapes, poses, 4), &format!("{}, serial any collision", name));
+    check_bool(expected, parallel_any_collision(shapes, poses, 4), &format!("{}, parallel any collision", name));
+}
+
+// a random scene, the same shapes on a grid too wide for any of them to touch, and that grid with
+// a single colliding pair
+fn check_any_collision_scenes(n: usize) {
+    let (shapes, _) = primitives_and_compounds(n);
+    let poses: Vec<_> = (0..n).map(|_| LieGroupISE3q::new_random()).collect();
+    check_any_collision(&shapes, &poses, "random scene");
+    let mut grid: Vec<_> = (0..n).map(|k| {
+        let cell = V3::new((k % 10) as f64, ((k / 10) % 10) as f64, (k / 100) as f64);
+        LieGroupISE3q::new(Isometry::from_parts(cell.into(), LieGroupISE3q::new_random().0.rotation))
+    }).collect();
+    check_bool(false, !parallel_double_phase_collision_check(&shapes, &grid, 4).is_empty(), "grid scene is free");
+    check_any_collision(&shapes, &grid, "grid scene");
+    // shape 0 is a sphere and shape 1 a cuboid, both hold their origin
+    grid[1] = grid[0];
+    check_bool(true, !parallel_double_phase_collision_check(&shapes, &grid, 4).is_empty(), "grid scene with one contact collides");
+    check_any_collision(&shapes, &grid, "grid scene with one contact");
+}
+
+// filtering inside the traversal must give the unfiltered contacts with the filter applied afterwards
+fn check_filtered(shapes: &[Shape], poses: &[LieGroupISE3q], filter: &PairFilter, name: &str) {
+    let pairs = |res: Vec<Contact>| -> Vec<(usize, usize)> { res.iter().map(|c| (c.i, c.j)).collect() };
+    let expected: Vec<(usize, usize)> = pairs(parallel_double_phase_collision_check(shapes, poses, 4)).into_iter().filter(|&(i, j)| filter.accepts(i, j)).collect();
+    check_pairs(&expected, &pairs(parallel_filtered_collision_check(shapes, poses, filter, 4)), &format!("{}, parallel filtered", name));
+    check_pairs(&expected, &pairs(serial_filtered_collision_check(shapes, poses, filter, 4)), &format!("{}, serial filtered", name));
+    check_bool(!expected.is_empty(), parallel_filtered_any_collision(shapes, poses, filter, 4), &format!("{}, parallel filtered any collision", name));
+    check_bool(!expected.is_empty(), serial_filtered_any_collision(shapes, poses, filter, 4), &format!("{}, serial filtered any collision", name));
+}
+
+// random groups and masks, with and without a predicate, and a filter that rejects every pair
+fn check_filters(n: usize) {
+    let mut rng = rand::thread_rng();
+    let (shapes, _) = primitives_and_compounds(n);
+    let poses: Vec<_> = (0..n).map(|_| LieGroupISE3q::new_random()).collect();
+    let filters: Vec<CollisionFilter> = (0..n).map(|_| CollisionFilter::new(1 << rng.gen_range(0..4), rng.gen_range(0..16))).collect();
+    check_filtered(&shapes, &poses, &PairFilter::new(&filters), "groups and masks");
+    let predicate = |i: usize, j: usize| !(i + j).is_multiple_of(3);
+    check_filtered(&shapes, &poses, &PairFilter::new(&filters).with_predicate(&predicate), "groups, masks and predicate");
+    let none = vec![CollisionFilter::new(1, 0); n];
+    check_filtered(&shapes, &poses, &PairFilter::new(&none), "filter rejecting every pair");
+}
+
+// contacts between two sets must be the contacts of the scene holding both, restricted to pairs across them
+fn check_bipartite(n: usize) {
+    let (shapes, _) = primitives_and_compounds(n);
+    let poses: Vec<_> = (0..n).map(|_| LieGroupISE3q::new_random()).collect();
+    let split = n / 3;
+    // a pair GJK could not settle may or may not survive either broad phase, leave those out on both sides
+    let settled = |res: Vec<Contact>| -> Vec<(usize, usize)> { res.iter().filter(|c| c.converged).map(|c| (c.i, c.j)).collect() };
+    let expected: Vec<(usize, usize)> = settled(parallel_double_phase_collision_check(&shapes, &poses, 4)).into_iter()
+        .filter(|&(i, j)| i < split && j >= split)
+        .map(|(i, j)| (i, j - split))
+        .collect();
+    let (shapes1, shapes2) = shapes.split_at(split);
+    let (poses1, poses2) = poses.split_at(split);
+    check_pairs(&expected, &settled(parallel_bipartite_collision_check(shapes1, poses1, shapes2, poses2, 4)), "parallel bipartite");
+    check_pairs(&expected, &settled(serial_bipartite_collision_check(shapes1, poses1, shapes2, poses2, 4)), "serial bipartite");
+}
+
+// a saved matrix must load back entry for entry, and a file whose entries do not fit its size must be refused
+fn check_acm(n: usize, samples: usize) {
+    let (shapes, _) = primitives_and_compounds(n);
+    // shapes crowded in a small box, so that pairs of every kind turn up
+    let mut acm = AllowedCollisionMatrix::from_sampling(&shapes, || (0..n).map(|_| {
+        let mut pose = LieGroupISE3q::new_random();
+        pose.0.translation.vector *= 0.2;
+        pose
+    }).collect(), samples, 4);
+    acm.allow(0, n - 1, AllowedReason::User);
+    acm.disallow(1, 2);
+    let path = std::env::temp_dir().join(format!("correctness_acm_{}.json", std::process::id()));
+    println!("Checking acm round trip");
+    acm.save_json(&path).unwrap();
+    let loaded = AllowedCollisionMatrix::load_json(&path).unwrap();
+    if let Some((i, j)) = all_pairs(n).into_iter().find(|&(i, j)| acm.reason(i, j) != loaded.reason(i, j)) {
+        panic!("pair ({}, {}) mismatch! saved: {:?}, loaded: {:?}", i, j, acm.reason(i, j), loaded.reason(i, j));
+    }
+    if loaded != acm {
+        panic!("loaded matrix differs from the saved one");
+    }
+    let counts = [AllowedReason::Always, AllowedReason::Never, AllowedReason::User]
+        .map(|r| all_pairs(n).into_iter().filter(|&(i, j)| acm.reason(i, j) == Some(r)).count());
+    println!("acm round trip passed, {:?} always/never/user pairs", counts);
+
+    println!("Checking acm with the wrong number of entries");
+    std::fs::write(&path, r#"{"n":4,"allowed":[null,"Always",null,"Never","User"]}"#).unwrap();
+    match AllowedCollisionMatrix::load_json(&path) {
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {}
+        res => panic!("a matrix of 4 shapes with 5 entries was not refused: {:?}", res),
+    }
+    std::fs::remove_file(&path).unwrap();
+    println!("acm with the wrong number of entries passed");
+}
+
+// warm-started GJK must find the same pairs within the threshold as a cold start, at the same distances,
+// frame after frame of a small motion
+fn check_gjk_cache(n: usize, frames: usize) {
+    let mut rng = rand::thread_rng();
+    let (shapes, _) = primitives_and_compounds(n);
+    let mut poses: Vec<_> = (0..n).map(|_| LieGroupISE3q::new_random()).collect();
+    let options = QueryOptions::default().with_penetration().with_margin(0.05);
+    // the same pairs every frame, so that every one of them is seeded from the previous frame
+    let pairs: Vec<(usize, usize)> = parallel_double_phase_proximity_check(&shapes, &poses, 4, 0.3).iter().map(|c| (c.i, c.j)).collect();
+    let (mut serial_cache, mut parallel_cache) = (GJKCache::new(), GJKCache::new());
+    for frame in 0..frames {
+        for pose in poses.iter_mut() {
+            pose.0.translation.vector += V3::new(rng.gen_range(-0.02..0.02), rng.gen_range(-0.02..0.02), rng.gen_range(-0.02..0.02));
+        }
+        let cold = parallel_narrow_phase_distance_with(&pairs, &shapes, &poses, &options);
+        for (warm, name) in [(serial_narrow_phase_check_cached_with(&pairs, &shapes, &poses, &mut serial_cache, &options), "serial cached"),
+                             (parallel_narrow_phase_check_cached_with(&pairs, &shapes, &poses, &mut parallel_cache, &options), "parallel cached")] {
+            let name = format!("{} frame {}", name, frame);
+            // a pair GJK could not settle from either start is within the threshold whatever its distance,
+            // leave those out on both sides
+            let unsettled: HashSet<(usize, usize)> = cold.iter().chain(&warm).filter(|c| !c.converged).map(|c| (c.i, c.j)).collect();
+            let expected: HashMap<(usize, usize), f64> = cold.iter()
+                .filter(|c| c.distance <= options.contact_threshold && !unsettled.contains(&(c.i, c.j)))
+                .map(|c| ((c.i, c.j), c.distance))
+                .collect();
+            let warm: Vec<&Contact> = warm.iter().filter(|c| !unsettled.contains(&(c.i, c.j))).collect();
+            check_pairs(&expected.keys().copied().collect::<Vec<_>>(), &warm.iter().map(|c| (c.i, c.j)).collect::<Vec<_>>(), &name);
+            // EPA between two curved surfaces runs out of iterations before it converges, penetration
+            // depths are only this close relative to the depth
+            let tol = |d: f64| DISTANCE_TOL + if d < 0.0 { 1e-3 * -d } else { 0.0 };
+            if let Some(c) = warm.iter().find(|c| (expected[&(c.i, c.j)] - c.distance).abs() > tol(c.distance)) {
+                panic!("{}: pair ({}, {}) distance mismatch! cold start: {}, warm start: {}", name, c.i, c.j, expected[&(c.i, c.j)], c.distance);
+            }
+        }
+    }
+}
+
+// hill climbing over the vertex graph of a large hull must reach the same support as a scan of every
+// vertex, from any starting vertex
+fn check_hill_climbing(n: usize, queries: usize) {
+    let mut rng = rand::thread_rng();
+    // points on an ellipsoid all end up on the hull
+    let points: Vec<V3> = (0..n).map(|_| {
+        let p = V3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)).normalize();
+        V3::new(0.3 * p.x, 0.1 * p.y, 0.05 * p.z)
+    }).collect();
+    let hull = ConvexHull::from_points(&points);
+    let vertices = hull.mesh().points.len();
+    println!("Checking hill climbing support on {} vertices", vertices);
+    assert!(vertices >= 500, "the hull has only {} vertices", vertices);
+    for _ in 0..queries {
+        let pose = LieGroupISE3q::new_random();
+        let dir = V3::new(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+        let expected = hull.mesh().points.iter()
+            .map(|p| (pose.0.rotation * V3::from_column_slice(p) + pose.0.translation.vector).dot(&dir))
+            .fold(f64::NEG_INFINITY, f64::max);
+        for start in [usize::MAX, rng.gen_range(0..vertices)] {
+            let mut vertex = start;
+            let support = hull.support_from(&dir, &pose, &mut vertex);
+            if (support.dot(&dir) - expected).abs() > 1e-12 {
+                panic!("support from vertex {} mismatch! climbed to {}, scan found {}", start, support.dot(&dir), expected);
+            }
+            let end = pose.0.rotation * V3::from_column_slice(&hull.mesh().points[vertex]) + pose.0.translation.vector;
+            if (end - support).norm() > 1e-12 {
+                panic!("support from vertex {} is not at the vertex {} it reports", start, vertex);
+            }
+        }
+    }
+    println!("hill climbing support passed, {} directions", queries);
+}
+
+// a BVH refit frame after frame must find the same contacts as one built fresh each frame, both when
+// subtrees are kept and when every grown subtree is rebuilt
+fn check_incremental(n: usize, frames: usize) {
+    let mut rng = rand::thread_rng();
+    let (shapes, _) = primitives_and_compounds(n);
+    for max_growth in [2.0, 1.0] {
+        let mut poses: Vec<_> = (0..n).map(|_| LieGroupISE3q::new_random()).collect();
+        let mut bvh = new_incremental_bvh(&shapes, &poses, 4);
+        bvh.max_growth = max_growth;
+        for frame in 0..frames {
+            for pose in poses.iter_mut() {
+                pose.0.translation.vector += V3::new(rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1));
+            }
+            // a pair GJK could not settle may or may not survive either broad phase, leave those out on both sides
+            let settled = |res: Vec<Contact>| -> Vec<(usize, usize)> { res.iter().filter(|c| c.converged).map(|c| (c.i, c.j)).collect() };
+            check_pairs(&settled(parallel_double_phase_collision_check(&shapes, &poses, 4)),
+                        &settled(parallel_incremental_collision_check(&mut bvh, &shapes, &poses)),
+                        &format!("incremental frame {}, max growth {}", frame, max_growth));
+        }
+    }
+}
+
+// settled pairs of a world query, by slot index
+fn world_pairs(res: &[(ShapeHandle, ShapeHandle, Contact)]) -> Vec<(usize, usize)> {
+    res.iter().filter(|(_, _, c)| c.converged).map(|(h1, h2, _)| (h1.index().min(h2.index()), h1.index().max(h2.index()))).collect()
+}
+
+// the world's queries against the free pipelines on its live shapes and poses
+fn check_world_queries(world: &mut CollisionWorld<Arc<Shape>>, margin: f64, name: &str) {
+    let handles: Vec<ShapeHandle> = world.handles().collect();
+    let shapes: Vec<Arc<Shape>> = handles.iter().map(|&h| world.shape(h).unwrap().clone()).collect();
+    let poses: Vec<LieGroupISE3q> = handles.iter().map(|&h| *world.pose(h).unwrap()).collect();
+    // a pair GJK could not settle may or may not survive either broad phase, leave those out on both sides
+    let by_slot = |res: Vec<Contact>| -> Vec<(usize, usize)> {
+        res.iter().filter(|c| c.converged).map(|c| {
+            let (a, b) = (handles[c.i].index(), handles[c.j].index());
+            (a.min(b), a.max(b))
+        }).collect()
+    };
+    let colliding = by_slot(parallel_double_phase_collision_check(&shapes, &poses, 4));
+    check_pairs(&colliding, &world_pairs(&world.collisions()), &format!("{}, world collisions", name));
+    for m in [margin, 4.0 * margin] {
+        let close = by_slot(parallel_double_phase_proximity_check(&shapes, &poses, 4, m));
+        check_pairs(&close, &world_pairs(&world.proximity(m)), &format!("{}, world proximity {}", name, m));
+    }
+    check_bool(!parallel_double_phase_collision_check(&shapes, &poses, 4).is_empty(), world.any_collision(), &format!("{}, world any collision", name));
+}
+
+// a world taken through inserts, removals and moves must answer like the free pipelines on what it
+// holds, and must reject handles of removed objects even once their slot is reused
+fn check_world(n: usize, frames: usize) {
+    let mut rng = rand::thread_rng();
+    let margin = 0.05;
+    let pool: Vec<Arc<Shape>> = primitives_and_compounds(n).0.into_iter().map(Arc::new).collect();
+    let mut world: CollisionWorld<Arc<Shape>> = CollisionWorld::with_margin(4, margin);
+    for shape in &pool {
+        world.insert(shape.clone(), LieGroupISE3q::new_random());
+    }
+    check_world_queries(&mut world, margin, "fresh world");
+    for frame in 0..frames {
+        let handles: Vec<ShapeHandle> = world.handles().collect();
+        let removed: Vec<ShapeHandle> = handles.iter().copied().filter(|_| rng.gen_bool(0.1)).collect();
+        for &h in &removed {
+            if world.remove(h).is_none() {
+                panic!("frame {}: removing live handle {:?} failed", frame, h);
+            }
+        }
+        for _ in 0..removed.len() {
+            world.insert(pool[rng.gen_range(0..n)].clone(), LieGroupISE3q::new_random());
+        }
+        let other = world.handles().next().unwrap();
+        for &h in &removed {
+            if world.contains(h) || world.set_pose(h, LieGroupISE3q::new_random()) || world.pose(h).is_some()
+                || world.shape(h).is_some() || world.distance(h, other).is_some() || world.remove(h).is_some() {
+                panic!("frame {}: stale handle {:?} accepted", frame, h);
+            }
+        }
+        check_world_queries(&mut world, margin, &format!("frame {} after inserts and removals", frame));
+
+        // moves alone refit the tree instead of rebuilding it
+        let handles: Vec<ShapeHandle> = world.handles().collect();
+        for &h in &handles {
+            let mut pose = *world.pose(h).unwrap();
+            pose.0.translation.vector += V3::new(rng.gen_range(-0.05..0.05), rng.gen_range(-0.05..0.05), rng.gen_range(-0.05..0.05));
+            world.set_pose(h, pose);
+        }
+        world.update();
+        check_world_queries(&mut world, margin, &format!("frame {} after moves", frame));
+    }
+    println!("world passed, {} objects", world.len());
+}
+
 fn main() {
-    let mut hulls = generate_random_hulls(10000, (50, 100), (V3::new(0.0, 0.0, 0.0), V3::new(1.0, 1.0, 1.0)));
+    let hulls = generate_random_hulls(10000, (50, 100), (V3::new(0.0, 0.0, 0.0), V3::new(1.0, 1.0, 1.0)));
     //let mut hull2 = generate_random_hulls(100, (50, 100), (V3::new(0.0, 0.0, 0.0), V3::new(1.0, 1.0, 1.0)));
     //hulls.append(&mut hull2);
     let parry_hulls = my_hulls_to_parry_hulls(&hulls);
@@ -45,5 +693,25 @@ fn main() {
     check(&c3, &c4, "my parallel narrow");
     check(&c3, &c5, "my serial double");
     check(&c3, &c6, "my parallel double");
+    check(&c3, &serial_flat_double_phase_collision_check(&hulls, &poses, 4), "my serial flat double");
+    check(&c3, &parallel_flat_double_phase_collision_check(&hulls, &poses, 4), "my parallel flat double");
+    check_any_collision(&hulls, &poses, "hulls");
+
+    check_degenerate_hulls(2000);
+    check_penetration(20000);
+    check_primitives_and_compounds(1500);
+    check_lbvh(20000);
+    check_flat_bvh(20000);
+    check_sah(20000);
+    check_time_of_impact(600, 50);
+    check_swept_pipelines(600, 50);
+    check_any_collision_scenes(1500);
+    check_filters(1500);
+    check_bipartite(1500);
+    check_incremental(1500, 5);
+    check_acm(60, 50);
+    check_gjk_cache(1500, 5);
+    check_hill_climbing(2000, 100000);
+    check_world(1500, 4);
 
 }
\ No newline at end of file
@@ -0,0 +1,69 @@
+use super::par_bvh::parallel_build_bvh;
+use super::structs::{AABB, BVHNode, rebuild_subtree};
+
+const DEFAULT_MAX_GROWTH: f64 = 2.0;
+
+// a BVH that survives across frames: bounds are refit bottom-up after the primitives move and
+// only subtrees whose boxes grew by more than max_growth since their build are rebuilt
+pub struct DynamicBVH {
+    root: Box<dyn BVHNode>,
+    aabbs: Vec<AABB>,
+    cut_off_size: usize,
+    pub max_growth: f64,
+}
+
+impl DynamicBVH {
+    pub fn new(aabbs: Vec<AABB>, cut_off_size: usize) -> Self {
+        let indices: Vec<usize> = (0..aabbs.len()).collect();
+        Self::from_indices(aabbs, indices, cut_off_size)
+    }
+
+    // only the listed primitives go into the tree, the other boxes are kept but never visited.
+    // with no primitives the root is an empty leaf and every query finds nothing.
+    pub fn from_indices(aabbs: Vec<AABB>, mut indices: Vec<usize>, cut_off_size: usize) -> Self {
+        let root = parallel_build_bvh(&mut indices, &aabbs, cut_off_size);
+        Self { root, aabbs, cut_off_size, max_growth: DEFAULT_MAX_GROWTH }
+    }
+
+    pub fn root(&self) -> &dyn BVHNode {
+        &*self.root
+    }
+
+    pub fn aabbs(&self) -> &[AABB] {
+        &self.aabbs
+    }
+
+    pub fn len(&self) -> usize {
+        self.aabbs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.aabbs.is_empty()
+    }
+
+    // changes the box of one primitive, call refit once all of them are updated
+    pub fn set_aabb(&mut self, index: usize, aabb: AABB) {
+        self.aabbs[index] = aabb;
+    }
+
+    pub fn cut_off_size(&self) -> usize {
+        self.cut_off_size
+    }
+
+    // replaces every box at once, the number of primitives must not change
+    pub fn set_aabbs(&mut self, aabbs: Vec<AABB>) {
+        assert_eq!(aabbs.len(), self.aabbs.len(),
+                   "refitting cannot change the number of primitives, rebuild instead");
+        self.aabbs = aabbs;
+    }
+
+    pub fn refit(&mut self) {
+        if self.root.refit(&self.aabbs, self.cut_off_size, self.max_growth, 0) {
+            self.root = rebuild_subtree(&*self.root, &self.aabbs, self.cut_off_size);
+        }
+    }
+
+    pub fn rebuild(&mut self) {
+        self.root = rebuild_subtree(&*self.root, &self.aabbs, self.cut_off_size);
+    }
+}
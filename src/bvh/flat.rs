@@ -0,0 +1,492 @@
+use std::cell::RefCell;
+use apollo_rust_spatial::vectors::V3;
+use rayon::join;
+use thread_local::ThreadLocal;
+use std::sync::atomic::{AtomicBool, Ordering};
+use super::par_bvh::parallel_split_at_axis_with;
+use super::srl_bvh::serial_split_at_axis_with;
+use super::structs::{AABB, BVHNode, BuildStrategy, LeafPairs, cross_leaf_pairs, within_leaf_pairs};
+
+const MAX_DEPTH: usize = 16;
+const BUILD_PARALLEL_THRESHOLD: usize = 4096;
+const TRAVERSAL_STACK_SIZE: usize = 64;
+const INTERNAL: usize = usize::MAX; // count of internal nodes, leaves may hold zero primitives
+
+// a node of a FlatBVH. internal nodes have count == INTERNAL, their left child is stored right after
+// them and offset is the index of their right child. leaves own indices[offset..offset + count].
+#[derive(Debug, Clone, Copy)]
+pub struct FlatNode {
+    pub aabb: AABB,
+    pub offset: usize,
+    pub count: usize,
+}
+
+impl FlatNode {
+    pub fn is_leaf(&self) -> bool {
+        self.count != INTERNAL
+    }
+}
+
+// BVH stored in two contiguous arrays: nodes in depth-first order and the primitive indices
+// of all leaves, each leaf owning one contiguous range of them. traversal does no virtual calls
+// and touches no per-leaf allocation.
+#[derive(Debug, Clone)]
+pub struct FlatBVH {
+    pub nodes: Vec<FlatNode>,
+    pub indices: Vec<usize>,
+    pub aabbs: Vec<AABB>, // box of every entry of indices, to filter leaf-leaf pairs
+}
+
+impl FlatBVH {
+    pub fn root(&self) -> &FlatNode {
+        &self.nodes[0]
+    }
+
+    pub fn leaf_indices(&self, node: &FlatNode) -> &[usize] {
+        &self.indices[node.offset..node.offset + node.count]
+    }
+
+    pub fn leaf_aabbs(&self, node: &FlatNode) -> &[AABB] {
+        &self.aabbs[node.offset..node.offset + node.count]
+    }
+
+    fn leaf_pairs<'a>(&'a self, n1: &'a FlatNode, other: &'a FlatBVH, n2: &'a FlatNode, leaf_pairs: LeafPairs) -> impl Iterator<Item = (usize, usize)> + 'a {
+        cross_leaf_pairs(self.leaf_indices(n1), self.leaf_aabbs(n1), other.leaf_indices(n2), other.leaf_aabbs(n2), leaf_pairs)
+    }
+
+    fn self_leaf_pairs<'a>(&'a self, node: &'a FlatNode) -> impl Iterator<Item = (usize, usize)> + 'a {
+        within_leaf_pairs(self.leaf_indices(node), self.leaf_aabbs(node))
+    }
+
+    // (left, right) child positions of an internal node
+    pub fn children(&self, node_index: usize) -> (usize, usize) {
+        (node_index + 1, self.nodes[node_index].offset)
+    }
+
+    // flattens a pointer-based tree, whatever builder produced it
+    pub fn from_node(root: &dyn BVHNode) -> Self {
+        let mut bvh = FlatBVH { nodes: Vec::new(), indices: Vec::new(), aabbs: Vec::new() };
+        bvh.push_node(root);
+        bvh
+    }
+
+    fn push_node(&mut self, node: &dyn BVHNode) {
+        let position = self.nodes.len();
+        if node.is_leaf() {
+            let leaf = node.leaf_indices().unwrap();
+            self.nodes.push(FlatNode { aabb: *node.aabb_ref(), offset: self.indices.len(), count: leaf.len() });
+            self.indices.extend_from_slice(leaf);
+            self.aabbs.extend_from_slice(node.leaf_aabbs().unwrap());
+            return;
+        }
+        self.nodes.push(FlatNode { aabb: *node.aabb_ref(), offset: 0, count: INTERNAL });
+        let (l, r) = node.children();
+        self.push_node(l.unwrap());
+        self.nodes[position].offset = self.nodes.len();
+        self.push_node(r.unwrap());
+    }
+
+    // recomputes every box bottom-up from all_aabbs, children always come after their parent
+    pub fn refit(&mut self, all_aabbs: &[AABB]) {
+        for (bb, &i) in self.aabbs.iter_mut().zip(&self.indices) {
+            *bb = all_aabbs[i];
+        }
+        for k in (0..self.nodes.len()).rev() {
+            let node = self.nodes[k];
+            self.nodes[k].aabb = if node.is_leaf() {
+                leaf_aabb(&self.indices[node.offset..node.offset + node.count], all_aabbs)
+            } else {
+                self.nodes[k + 1].aabb.union(&self.nodes[node.offset].aabb)
+            };
+        }
+    }
+}
+
+fn leaf_aabb(indices: &[usize], all_aabbs: &[AABB]) -> AABB {
+    let mut min_coords = V3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max_coords = V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for &i in indices {
+        min_coords = min_coords.inf(&all_aabbs[i].min_coords);
+        max_coords = max_coords.sup(&all_aabbs[i].max_coords);
+    }
+    AABB::new(min_coords, max_coords)
+}
+
+// appends the subtree over aabb_indices (which starts at position start of the final index
+// array) to nodes, node offsets are relative to the start of nodes
+fn serial_build_nodes(aabb_indices: &mut [usize], start: usize, all_aabbs: &[AABB], cut_off_size: usize,
+                      strategy: BuildStrategy, nodes: &mut Vec<FlatNode>) {
+    let position = nodes.len();
+    let leaf = FlatNode { aabb: leaf_aabb(aabb_indices, all_aabbs), offset: start, count: aabb_indices.len() };
+    if aabb_indices.len() <= cut_off_size {
+        nodes.push(leaf);
+        return;
+    }
+    let Some((left, right)) = serial_split_at_axis_with(aabb_indices, all_aabbs, strategy) else {
+        nodes.push(leaf);
+        return;
+    };
+    let left_len = left.len();
+    nodes.push(FlatNode { aabb: leaf.aabb, offset: 0, count: INTERNAL });
+    serial_build_nodes(left, start, all_aabbs, cut_off_size, strategy, nodes);
+    nodes[position].offset = nodes.len();
+    serial_build_nodes(right, start + left_len, all_aabbs, cut_off_size, strategy, nodes);
+}
+
+pub fn serial_build_flat_bvh(aabb_indices: &mut [usize], all_aabbs: &[AABB], cut_off_size: usize) -> FlatBVH {
+    serial_build_flat_bvh_with(aabb_indices, all_aabbs, cut_off_size, BuildStrategy::Midpoint)
+}
+
+// the leaves index straight into aabb_indices, which ends up partitioned in leaf order
+pub fn serial_build_flat_bvh_with(aabb_indices: &mut [usize], all_aabbs: &[AABB], cut_off_size: usize,
+                                  strategy: BuildStrategy) -> FlatBVH {
+    let mut nodes = Vec::with_capacity(2 * aabb_indices.len() / cut_off_size.max(1) + 1);
+    serial_build_nodes(aabb_indices, 0, all_aabbs, cut_off_size, strategy, &mut nodes);
+    let aabbs = aabb_indices.iter().map(|&i| all_aabbs[i]).collect();
+    FlatBVH { nodes, indices: aabb_indices.to_vec(), aabbs }
+}
+
+// large subtrees are built as independent fragments by two rayon tasks and then spliced after
+// their parent, shifting the right child positions of the moved fragments
+fn parallel_build_nodes(aabb_indices: &mut [usize], start: usize, all_aabbs: &[AABB], cut_off_size: usize,
+                        strategy: BuildStrategy) -> Vec<FlatNode> {
+    // the same leaf test as serial_build_nodes comes first, so both builds split the same nodes
+    if aabb_indices.len() <= cut_off_size {
+        return vec![FlatNode { aabb: leaf_aabb(aabb_indices, all_aabbs), offset: start, count: aabb_indices.len() }];
+    }
+    if aabb_indices.len() <= BUILD_PARALLEL_THRESHOLD {
+        let mut nodes = Vec::new();
+        serial_build_nodes(aabb_indices, start, all_aabbs, cut_off_size, strategy, &mut nodes);
+        return nodes;
+    }
+    let Some((left, right)) = parallel_split_at_axis_with(aabb_indices, all_aabbs, strategy) else {
+        return vec![FlatNode { aabb: leaf_aabb(aabb_indices, all_aabbs), offset: start, count: aabb_indices.len() }];
+    };
+    let left_len = left.len();
+    let (left_nodes, right_nodes) = join(
+        || parallel_build_nodes(left, start, all_aabbs, cut_off_size, strategy),
+        || parallel_build_nodes(right, start + left_len, all_aabbs, cut_off_size, strategy),
+    );
+    let right_position = 1 + left_nodes.len();
+    let mut nodes = Vec::with_capacity(right_position + right_nodes.len());
+    nodes.push(FlatNode { aabb: left_nodes[0].aabb.union(&right_nodes[0].aabb), offset: right_position, count: INTERNAL });
+    nodes.extend(left_nodes.into_iter().map(|n| shifted(n, 1)));
+    nodes.extend(right_nodes.into_iter().map(|n| shifted(n, right_position)));
+    nodes
+}
+
+fn shifted(node: FlatNode, by: usize) -> FlatNode {
+    if node.is_leaf() { node } else { FlatNode { offset: node.offset + by, ..node } }
+}
+
+pub fn parallel_build_flat_bvh(aabb_indices: &mut [usize], all_aabbs: &[AABB], cut_off_size: usize) -> FlatBVH {
+    parallel_build_flat_bvh_with(aabb_indices, all_aabbs, cut_off_size, BuildStrategy::Midpoint)
+}
+
+pub fn parallel_build_flat_bvh_with(aabb_indices: &mut [usize], all_aabbs: &[AABB], cut_off_size: usize,
+                                    strategy: BuildStrategy) -> FlatBVH {
+    let nodes = parallel_build_nodes(aabb_indices, 0, all_aabbs, cut_off_size, strategy);
+    let aabbs = aabb_indices.iter().map(|&i| all_aabbs[i]).collect();
+    FlatBVH { nodes, indices: aabb_indices.to_vec(), aabbs }
+}
+
+pub fn serial_flat_broad_phase_check(t1: &FlatBVH, t2: &FlatBVH) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    serial_flat_pairs(t1, t2, 0, 0, LeafPairs::Ordered, &mut out);
+    out
+}
+
+// pairs of a tree with itself, (L, L), (R, R) and (L, R) are each visited once
+pub fn serial_flat_self_broad_phase_check(t: &FlatBVH) -> Vec<(usize, usize)> {
+    let mut out = Vec::new();
+    let mut stack: Vec<usize> = Vec::with_capacity(TRAVERSAL_STACK_SIZE);
+    stack.push(0);
+    while let Some(a) = stack.pop() {
+        let node = &t.nodes[a];
+        if node.is_leaf() {
+            out.extend(t.self_leaf_pairs(node));
+            continue;
+        }
+        let (l, r) = t.children(a);
+        serial_flat_pairs(t, t, l, r, LeafPairs::Sorted, &mut out);
+        stack.push(r);
+        stack.push(l);
+    }
+    out
+}
+
+// iterative traversal with an explicit stack of node pairs
+fn serial_flat_pairs(t1: &FlatBVH, t2: &FlatBVH, root1: usize, root2: usize, leaf_pairs: LeafPairs,
+                     out: &mut Vec<(usize, usize)>) {
+    let mut stack: Vec<(usize, usize)> = Vec::with_capacity(TRAVERSAL_STACK_SIZE);
+    stack.push((root1, root2));
+    while let Some((a, b)) = stack.pop() {
+        let (n1, n2) = (&t1.nodes[a], &t2.nodes[b]);
+        if !n1.aabb.intersects(&n2.aabb) {
+            continue;
+        }
+        match (n1.is_leaf(), n2.is_leaf()) {
+            (true, true) => out.extend(t1.leaf_pairs(n1, t2, n2, leaf_pairs)),
+            (true, false) => {
+                let (l, r) = t2.children(b);
+                stack.push((a, r));
+                stack.push((a, l));
+            }
+            (false, true) => {
+                let (l, r) = t1.children(a);
+                stack.push((r, b));
+                stack.push((l, b));
+            }
+            (false, false) => {
+                let (l1, r1) = t1.children(a);
+                let (l2, r2) = t2.children(b);
+                stack.push((r1, r2));
+                stack.push((r1, l2));
+                stack.push((l1, r2));
+                stack.push((l1, l2));
+            }
+        }
+    }
+}
+
+pub fn parallel_flat_broad_phase_check(t1: &FlatBVH, t2: &FlatBVH) -> Vec<(usize, usize)> {
+    let tl: ThreadLocal<RefCell<Vec<(usize, usize)>>> = ThreadLocal::new();
+    flat_gather(t1, t2, 0, 0, 0, LeafPairs::Ordered, &tl);
+    let mut out = Vec::new();
+    for cell in tl.into_iter() {
+        out.extend(cell.into_inner());
+    }
+    out
+}
+
+// pairs of a tree with itself, (L, L), (R, R) and (L, R) are each visited once
+pub fn parallel_flat_self_broad_phase_check(t: &FlatBVH) -> Vec<(usize, usize)> {
+    let tl: ThreadLocal<RefCell<Vec<(usize, usize)>>> = ThreadLocal::new();
+    flat_self_gather(t, 0, 0, &tl);
+    let mut out = Vec::new();
+    for cell in tl.into_iter() {
+        out.extend(cell.into_inner());
+    }
+    out
+}
+
+fn flat_self_gather(t: &FlatBVH, a: usize, depth: usize, tl: &ThreadLocal<RefCell<Vec<(usize, usize)>>>) {
+    let node = &t.nodes[a];
+    if node.is_leaf() {
+        let local = tl.get_or(|| RefCell::new(Vec::new()));
+        local.borrow_mut().extend(t.self_leaf_pairs(node));
+        return;
+    }
+    let (l, r) = t.children(a);
+    if depth < MAX_DEPTH {
+        join(
+            || join(|| flat_self_gather(t, l, depth + 1, tl), || flat_self_gather(t, r, depth + 1, tl)),
+            || flat_gather(t, t, l, r, depth + 1, LeafPairs::Sorted, tl),
+        );
+    } else {
+        flat_self_gather(t, l, depth + 1, tl);
+        flat_self_gather(t, r, depth + 1, tl);
+        flat_gather(t, t, l, r, depth + 1, LeafPairs::Sorted, tl);
+    }
+}
+
+fn flat_gather(t1: &FlatBVH, t2: &FlatBVH, a: usize, b: usize, depth: usize, leaf_pairs: LeafPairs,
+               tl: &ThreadLocal<RefCell<Vec<(usize, usize)>>>) {
+    let (n1, n2) = (&t1.nodes[a], &t2.nodes[b]);
+    if !n1.aabb.intersects(&n2.aabb) {
+        return;
+    }
+    match (n1.is_leaf(), n2.is_leaf()) {
+        (true, true) => {
+            let local = tl.get_or(|| RefCell::new(Vec::new()));
+            local.borrow_mut().extend(t1.leaf_pairs(n1, t2, n2, leaf_pairs));
+        }
+        (true, false) => {
+            let (l, r) = t2.children(b);
+            flat_gather(t1, t2, a, l, depth + 1, leaf_pairs, tl);
+            flat_gather(t1, t2, a, r, depth + 1, leaf_pairs, tl);
+        }
+        (false, true) => {
+            let (l, r) = t1.children(a);
+            flat_gather(t1, t2, l, b, depth + 1, leaf_pairs, tl);
+            flat_gather(t1, t2, r, b, depth + 1, leaf_pairs, tl);
+        }
+        (false, false) => {
+            let (l1, r1) = t1.children(a);
+            let (l2, r2) = t2.children(b);
+            if depth < MAX_DEPTH {
+                join(
+                    || {
+                        flat_gather(t1, t2, l1, l2, depth + 1, leaf_pairs, tl);
+                        flat_gather(t1, t2, l1, r2, depth + 1, leaf_pairs, tl);
+                    },
+                    || {
+                        flat_gather(t1, t2, r1, l2, depth + 1, leaf_pairs, tl);
+                        flat_gather(t1, t2, r1, r2, depth + 1, leaf_pairs, tl);
+                    },
+                );
+            } else {
+                flat_gather(t1, t2, l1, l2, depth + 1, leaf_pairs, tl);
+                flat_gather(t1, t2, l1, r2, depth + 1, leaf_pairs, tl);
+                flat_gather(t1, t2, r1, l2, depth + 1, leaf_pairs, tl);
+                flat_gather(t1, t2, r1, r2, depth + 1, leaf_pairs, tl);
+            }
+        }
+    }
+}
+
+// early-exit traversal: returns as soon as `test` accepts a candidate pair
+pub fn serial_flat_broad_phase_any<F: Fn(usize, usize) -> bool>(t1: &FlatBVH, t2: &FlatBVH, test: &F) -> bool {
+    serial_flat_any(t1, t2, 0, 0, LeafPairs::Ordered, test)
+}
+
+// early-exit counterpart of serial_flat_self_broad_phase_check
+pub fn serial_flat_self_broad_phase_any<F: Fn(usize, usize) -> bool>(t: &FlatBVH, test: &F) -> bool {
+    let mut stack: Vec<usize> = Vec::with_capacity(TRAVERSAL_STACK_SIZE);
+    stack.push(0);
+    while let Some(a) = stack.pop() {
+        let node = &t.nodes[a];
+        if node.is_leaf() {
+            if t.self_leaf_pairs(node).any(|(i, j)| test(i, j)) {
+                return true;
+            }
+            continue;
+        }
+        let (l, r) = t.children(a);
+        if serial_flat_any(t, t, l, r, LeafPairs::Sorted, test) {
+            return true;
+        }
+        stack.push(r);
+        stack.push(l);
+    }
+    false
+}
+
+fn serial_flat_any<F: Fn(usize, usize) -> bool>(t1: &FlatBVH, t2: &FlatBVH, root1: usize, root2: usize,
+                                                leaf_pairs: LeafPairs, test: &F) -> bool {
+    let mut stack: Vec<(usize, usize)> = Vec::with_capacity(TRAVERSAL_STACK_SIZE);
+    stack.push((root1, root2));
+    while let Some((a, b)) = stack.pop() {
+        let (n1, n2) = (&t1.nodes[a], &t2.nodes[b]);
+        if !n1.aabb.intersects(&n2.aabb) {
+            continue;
+        }
+        match (n1.is_leaf(), n2.is_leaf()) {
+            (true, true) => {
+                if t1.leaf_pairs(n1, t2, n2, leaf_pairs).any(|(i, j)| test(i, j)) {
+                    return true;
+                }
+            }
+            (true, false) => {
+                let (l, r) = t2.children(b);
+                stack.push((a, r));
+                stack.push((a, l));
+            }
+            (false, true) => {
+                let (l, r) = t1.children(a);
+                stack.push((r, b));
+                stack.push((l, b));
+            }
+            (false, false) => {
+                let (l1, r1) = t1.children(a);
+                let (l2, r2) = t2.children(b);
+                stack.push((r1, r2));
+                stack.push((r1, l2));
+                stack.push((l1, r2));
+                stack.push((l1, l2));
+            }
+        }
+    }
+    false
+}
+
+// early-exit traversal: every rayon task stops once one of them finds a pair accepted by `test`
+pub fn parallel_flat_broad_phase_any<F: Fn(usize, usize) -> bool + Sync>(t1: &FlatBVH, t2: &FlatBVH, test: &F) -> bool {
+    let found = AtomicBool::new(false);
+    flat_gather_any(t1, t2, 0, 0, 0, LeafPairs::Ordered, test, &found);
+    found.into_inner()
+}
+
+// early-exit counterpart of parallel_flat_self_broad_phase_check
+pub fn parallel_flat_self_broad_phase_any<F: Fn(usize, usize) -> bool + Sync>(t: &FlatBVH, test: &F) -> bool {
+    let found = AtomicBool::new(false);
+    flat_self_gather_any(t, 0, 0, test, &found);
+    found.into_inner()
+}
+
+fn flat_self_gather_any<F: Fn(usize, usize) -> bool + Sync>(t: &FlatBVH, a: usize, depth: usize, test: &F, found: &AtomicBool) {
+    if found.load(Ordering::Relaxed) {
+        return;
+    }
+    let node = &t.nodes[a];
+    if node.is_leaf() {
+        if t.self_leaf_pairs(node).any(|(i, j)| test(i, j)) {
+            found.store(true, Ordering::Relaxed);
+        }
+        return;
+    }
+    let (l, r) = t.children(a);
+    if depth < MAX_DEPTH {
+        join(
+            || join(|| flat_self_gather_any(t, l, depth + 1, test, found), || flat_self_gather_any(t, r, depth + 1, test, found)),
+            || flat_gather_any(t, t, l, r, depth + 1, LeafPairs::Sorted, test, found),
+        );
+    } else {
+        flat_self_gather_any(t, l, depth + 1, test, found);
+        flat_self_gather_any(t, r, depth + 1, test, found);
+        flat_gather_any(t, t, l, r, depth + 1, LeafPairs::Sorted, test, found);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn flat_gather_any<F: Fn(usize, usize) -> bool + Sync>(t1: &FlatBVH, t2: &FlatBVH, a: usize, b: usize, depth: usize,
+                                                        leaf_pairs: LeafPairs, test: &F, found: &AtomicBool) {
+    let (n1, n2) = (&t1.nodes[a], &t2.nodes[b]);
+    if found.load(Ordering::Relaxed) || !n1.aabb.intersects(&n2.aabb) {
+        return;
+    }
+    match (n1.is_leaf(), n2.is_leaf()) {
+        (true, true) => {
+            for (i, j) in t1.leaf_pairs(n1, t2, n2, leaf_pairs) {
+                if found.load(Ordering::Relaxed) {
+                    return;
+                }
+                if test(i, j) {
+                    found.store(true, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+        (true, false) => {
+            let (l, r) = t2.children(b);
+            flat_gather_any(t1, t2, a, l, depth + 1, leaf_pairs, test, found);
+            flat_gather_any(t1, t2, a, r, depth + 1, leaf_pairs, test, found);
+        }
+        (false, true) => {
+            let (l, r) = t1.children(a);
+            flat_gather_any(t1, t2, l, b, depth + 1, leaf_pairs, test, found);
+            flat_gather_any(t1, t2, r, b, depth + 1, leaf_pairs, test, found);
+        }
+        (false, false) => {
+            let (l1, r1) = t1.children(a);
+            let (l2, r2) = t2.children(b);
+            if depth < MAX_DEPTH {
+                join(
+                    || {
+                        flat_gather_any(t1, t2, l1, l2, depth + 1, leaf_pairs, test, found);
+                        flat_gather_any(t1, t2, l1, r2, depth + 1, leaf_pairs, test, found);
+                    },
+                    || {
+                        flat_gather_any(t1, t2, r1, l2, depth + 1, leaf_pairs, test, found);
+                        flat_gather_any(t1, t2, r1, r2, depth + 1, leaf_pairs, test, found);
+                    },
+                );
+            } else {
+                flat_gather_any(t1, t2, l1, l2, depth + 1, leaf_pairs, test, found);
+                flat_gather_any(t1, t2, l1, r2, depth + 1, leaf_pairs, test, found);
+                flat_gather_any(t1, t2, r1, l2, depth + 1, leaf_pairs, test, found);
+                flat_gather_any(t1, t2, r1, r2, depth + 1, leaf_pairs, test, found);
+            }
+        }
+    }
+}
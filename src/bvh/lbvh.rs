@@ -0,0 +1,128 @@
+use apollo_rust_spatial::vectors::V3;
+use rayon::prelude::*;
+use super::structs::{AABB, BVHNode, BVHInternalNode, BVHLeafNode};
+
+// 10 bits per axis, interleaved into a 30 bit code
+const MORTON_BITS: u32 = 10;
+const RADIX_BITS: u32 = 10;
+const RADIX: usize = 1 << RADIX_BITS;
+const SORT_CHUNK_SIZE: usize = 8192;
+const LBVH_PARALLEL_THRESHOLD: usize = 1024;
+
+// spreads the low 10 bits of v so that two zero bits separate consecutive bits
+fn expand_bits(v: u32) -> u32 {
+    let mut v = v & 0x3ff;
+    v = (v | (v << 16)) & 0x030000ff;
+    v = (v | (v << 8)) & 0x0300f00f;
+    v = (v | (v << 4)) & 0x030c30c3;
+    v = (v | (v << 2)) & 0x09249249;
+    v
+}
+
+// morton code of p quantized on a 1024^3 grid spanning [min, min + extent]
+pub fn morton_code(p: &V3, min: &V3, extent: &V3) -> u32 {
+    let scale = ((1 << MORTON_BITS) - 1) as f64;
+    let q = |axis: usize| {
+        if extent[axis] <= 0.0 { return 0; }
+        (((p[axis] - min[axis]) / extent[axis]) * scale).clamp(0.0, scale) as u32
+    };
+    (expand_bits(q(0)) << 2) | (expand_bits(q(1)) << 1) | expand_bits(q(2))
+}
+
+// stable LSD radix sort on the code. every chunk counting-sorts its items by the current digit
+// in parallel, then each digit's output is the concatenation of that digit's run over all chunks.
+fn parallel_radix_sort(mut items: Vec<(u32, usize)>) -> Vec<(u32, usize)> {
+    let mut shift = 0;
+    while shift < 3 * MORTON_BITS {
+        let digit = |item: &(u32, usize)| ((item.0 >> shift) as usize) & (RADIX - 1);
+        let runs: Vec<_> = items
+            .par_chunks(SORT_CHUNK_SIZE)
+            .map(|chunk| {
+                let mut offsets = vec![0; RADIX + 1];
+                for item in chunk {
+                    offsets[digit(item) + 1] += 1;
+                }
+                for d in 0..RADIX {
+                    offsets[d + 1] += offsets[d];
+                }
+                let mut cursor = offsets.clone();
+                let mut sorted = vec![(0, 0); chunk.len()];
+                for item in chunk {
+                    let d = digit(item);
+                    sorted[cursor[d]] = *item;
+                    cursor[d] += 1;
+                }
+                (sorted, offsets)
+            })
+            .collect();
+        items = (0..RADIX)
+            .into_par_iter()
+            .flat_map_iter(|d| runs.iter().flat_map(move |(sorted, offsets)| sorted[offsets[d]..offsets[d + 1]].iter().copied()))
+            .collect();
+        shift += RADIX_BITS;
+    }
+    items
+}
+
+// index of the last item of the left child: the codes in sorted are split where their
+// highest differing bit flips, identical codes are split in the middle
+fn find_split(sorted: &[(u32, usize)]) -> usize {
+    let first = sorted[0].0;
+    let last = sorted[sorted.len() - 1].0;
+    if first == last {
+        return sorted.len() / 2 - 1;
+    }
+    let common_prefix = (first ^ last).leading_zeros();
+    // binary search for the last code sharing more than common_prefix bits with first
+    let (mut split, mut step) = (0, sorted.len() - 1);
+    loop {
+        step = step.div_ceil(2);
+        let candidate = split + step;
+        if candidate < sorted.len() - 1 && (first ^ sorted[candidate].0).leading_zeros() > common_prefix {
+            split = candidate;
+        }
+        if step <= 1 { break; }
+    }
+    split
+}
+
+fn build_range(sorted: &[(u32, usize)], all_aabbs: &[AABB], cut_off_size: usize) -> Box<dyn BVHNode> {
+    if sorted.len() <= cut_off_size.max(1) {
+        return Box::new(BVHLeafNode::new(sorted.iter().map(|&(_, i)| i).collect(), all_aabbs));
+    }
+    let (left, right) = sorted.split_at(find_split(sorted) + 1);
+    let (l, r) = if sorted.len() > LBVH_PARALLEL_THRESHOLD {
+        rayon::join(
+            || build_range(left, all_aabbs, cut_off_size),
+            || build_range(right, all_aabbs, cut_off_size),
+        )
+    } else {
+        (build_range(left, all_aabbs, cut_off_size), build_range(right, all_aabbs, cut_off_size))
+    };
+    let node_aabb = l.union_aabb(&*r);
+    Box::new(BVHInternalNode::new(node_aabb, l, r))
+}
+
+// linear BVH: primitives are ordered by the morton code of their AABB center with a
+// parallel radix sort, the hierarchy is then emitted top-down from the sorted codes with
+// every subtree built as its own rayon task. aabb_indices is left in morton order.
+pub fn parallel_build_lbvh(aabb_indices: &mut [usize], all_aabbs: &[AABB], cut_off_size: usize) -> Box<dyn BVHNode> {
+    let (min_v, max_v) = aabb_indices
+        .par_iter()
+        .map(|&i| (all_aabbs[i].center, all_aabbs[i].center))
+        .reduce(
+            || (
+                V3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+                V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |(min_a, max_a), (min_b, max_b)| (min_a.inf(&min_b), max_a.sup(&max_b)),
+        );
+    let extent = max_v - min_v;
+    let codes: Vec<(u32, usize)> = aabb_indices
+        .par_iter()
+        .map(|&i| (morton_code(&all_aabbs[i].center, &min_v, &extent), i))
+        .collect();
+    let sorted = parallel_radix_sort(codes);
+    aabb_indices.par_iter_mut().zip(sorted.par_iter()).for_each(|(dst, &(_, i))| *dst = i);
+    build_range(&sorted, all_aabbs, cut_off_size)
+}
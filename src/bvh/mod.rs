@@ -1,4 +1,6 @@
 pub mod par_bvh;
 pub mod structs;
 pub mod srl_bvh;
-
+pub mod dynamic;
+pub mod lbvh;
+pub mod flat;
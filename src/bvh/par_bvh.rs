@@ -1,11 +1,7 @@
-use std::sync::Mutex;
 use apollo_rust_spatial::vectors::V3;
 use rayon::prelude::*;
-use super::structs::{AABB, BVHNode, BVHInternalNode, BVHLeafNode};
-use rayon::slice::ParallelSlice;
-use rayon::slice::ParallelSliceMut;
-use std::collections::HashSet;
-use crate::bvh::srl_bvh::{serial_longest_extent_axis, serial_split_at_axis};
+use super::structs::{AABB, BVHNode, BVHInternalNode, BVHLeafNode, BuildStrategy, LeafPairs, leaf_node_pairs, leaf_node_self_pairs};
+use crate::bvh::srl_bvh::{add_to_sah_bins, best_sah_split, empty_sah_bins, merge_sah_bins, serial_split_at_axis_with};
 const MAX_DEPTH: usize = 16;
 
 // essentially divide-and-conquer in parallel
@@ -52,12 +48,53 @@ fn parallel_split_at_axis<'a>(aabb_indices: &'a mut [usize], all_aabbs:&[AABB],
     (left_slice, right_slice)
 }
 
+// centroid bounds and bin accumulation are both parallel reductions, every task fills its own bins
+fn parallel_sah_axis(aabb_indices: &[usize], all_aabbs: &[AABB]) -> Option<(usize, f64)> {
+    let (c_min, c_max) = aabb_indices
+        .par_iter()
+        .map(|&i| (all_aabbs[i].center, all_aabbs[i].center))
+        .reduce(
+            || (
+                V3::new(f64::INFINITY,  f64::INFINITY,  f64::INFINITY),
+                V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            ),
+            |(min_a, max_a), (min_b, max_b)| (min_a.inf(&min_b), max_a.sup(&max_b)),
+        );
+    let bins = aabb_indices
+        .par_iter()
+        .fold(empty_sah_bins, |mut bins, &i| {
+            add_to_sah_bins(&mut bins, &all_aabbs[i], &c_min, &c_max);
+            bins
+        })
+        .reduce(empty_sah_bins, |a, b| merge_sah_bins(&a, &b));
+    best_sah_split(&bins, &c_min, &c_max)
+}
+
+// parallel counterpart of serial_split_at_axis_with
+pub fn parallel_split_at_axis_with<'a>(aabb_indices: &'a mut [usize], all_aabbs:&[AABB], strategy: BuildStrategy)->Option<(&'a mut [usize], &'a mut [usize])>{
+    let (axis, midpoint) = match strategy {
+        BuildStrategy::Midpoint => Some(parallel_longest_extent_axis(aabb_indices, all_aabbs)),
+        BuildStrategy::Sah => parallel_sah_axis(aabb_indices, all_aabbs),
+    }?;
+    let (left, right) = parallel_split_at_axis(aabb_indices, all_aabbs, axis, midpoint);
+    (!left.is_empty() && !right.is_empty()).then_some((left, right))
+}
+
 const BUILD_PARALLEL_THRESHOLD: usize = 4096;
 
 pub fn parallel_build_bvh(
     aabb_indices: &mut [usize],
     all_aabbs:      &[AABB],
     cut_off_size:   usize,
+) -> Box<dyn BVHNode> {
+    parallel_build_bvh_with(aabb_indices, all_aabbs, cut_off_size, BuildStrategy::Midpoint)
+}
+
+pub fn parallel_build_bvh_with(
+    aabb_indices: &mut [usize],
+    all_aabbs:      &[AABB],
+    cut_off_size:   usize,
+    strategy:       BuildStrategy,
 ) -> Box<dyn BVHNode> {
     // 1) check size up front
     let n = aabb_indices.len();
@@ -70,34 +107,24 @@ pub fn parallel_build_bvh(
 
     // 2) split path by size
     if do_parallel {
-        // – compute axis/median in parallel
-        let (axis, midpoint) =
-            parallel_longest_extent_axis(aabb_indices, all_aabbs);
-
-        // – this mutably borrows `aabb_indices`
-        let (left, right) =
-            parallel_split_at_axis(aabb_indices, all_aabbs, axis, midpoint);
-        if left.is_empty() || right.is_empty() {
+        // – compute the split and partition in parallel, this mutably borrows `aabb_indices`
+        let Some((left, right)) = parallel_split_at_axis_with(aabb_indices, all_aabbs, strategy) else {
             return Box::new(BVHLeafNode::new(aabb_indices.to_vec(), all_aabbs));
-        }
+        };
         // now spawn the two big recursive tasks
         let (l, r) = rayon::join(
-            || parallel_build_bvh(left,  all_aabbs, cut_off_size),
-            || parallel_build_bvh(right, all_aabbs, cut_off_size),
+            || parallel_build_bvh_with(left,  all_aabbs, cut_off_size, strategy),
+            || parallel_build_bvh_with(right, all_aabbs, cut_off_size, strategy),
         );
         let node_aabb = l.union_aabb(&*r);
         Box::new(BVHInternalNode::new(node_aabb, l, r))
     } else {
         // serial fallback: no mutable/immutable conflict
-        let (axis, midpoint) =
-            serial_longest_extent_axis(aabb_indices, all_aabbs);
-        let (left, right) =
-            serial_split_at_axis(aabb_indices, all_aabbs, axis, midpoint);
-        if left.is_empty() || right.is_empty() {
+        let Some((left, right)) = serial_split_at_axis_with(aabb_indices, all_aabbs, strategy) else {
             return Box::new(BVHLeafNode::new(aabb_indices.to_vec(), all_aabbs));
-        }
-        let l = parallel_build_bvh(left,  all_aabbs, cut_off_size);
-        let r = parallel_build_bvh(right, all_aabbs, cut_off_size);
+        };
+        let l = parallel_build_bvh_with(left,  all_aabbs, cut_off_size, strategy);
+        let r = parallel_build_bvh_with(right, all_aabbs, cut_off_size, strategy);
         let node_aabb = l.union_aabb(&*r);
         Box::new(BVHInternalNode::new(node_aabb, l, r))
     }
@@ -106,6 +133,7 @@ pub fn parallel_build_bvh(
 use rayon::join;
 use thread_local::ThreadLocal;
 use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 pub fn parallel_broad_phase_check(
     s1: &dyn BVHNode,
@@ -115,7 +143,7 @@ pub fn parallel_broad_phase_check(
     let tl: ThreadLocal<RefCell<Vec<(usize, usize)>>> = ThreadLocal::new();
 
     // recurse & fill thread-local buffers
-    gather(s1, s2, 0, &tl);
+    gather(s1, s2, 0, LeafPairs::Ordered, &|_, _| true, &tl);
 
     // consume the ThreadLocal, extract each Vec, and flatten them
     let mut out = Vec::new();
@@ -125,10 +153,72 @@ pub fn parallel_broad_phase_check(
     out
 }
 
-fn gather(
+// pairs between trees built over two different shape sets, i indexes the set of s1 and j the set of s2
+pub fn parallel_bipartite_broad_phase_check(
+    s1: &dyn BVHNode,
+    s2: &dyn BVHNode,
+) -> Vec<(usize, usize)> {
+    let tl: ThreadLocal<RefCell<Vec<(usize, usize)>>> = ThreadLocal::new();
+    gather(s1, s2, 0, LeafPairs::All, &|_, _| true, &tl);
+    let mut out = Vec::new();
+    for cell in tl.into_iter() {
+        out.extend(cell.into_inner());
+    }
+    out
+}
+
+// pairs of a tree with itself: (L, L), (R, R) and (L, R) are each visited once instead of
+// traversing the tree against itself and discarding the mirrored half of the pairs
+pub fn parallel_self_broad_phase_check(node: &dyn BVHNode) -> Vec<(usize, usize)> {
+    parallel_self_broad_phase_check_filtered(node, &|_, _| true)
+}
+
+// same as parallel_self_broad_phase_check, but a pair is only emitted if accept(i, j) holds.
+// the test runs inside the traversal, so rejected pairs are never collected.
+pub fn parallel_self_broad_phase_check_filtered<F: Fn(usize, usize) -> bool + Sync>(
+    node: &dyn BVHNode,
+    accept: &F,
+) -> Vec<(usize, usize)> {
+    let tl: ThreadLocal<RefCell<Vec<(usize, usize)>>> = ThreadLocal::new();
+    self_gather(node, 0, accept, &tl);
+    let mut out = Vec::new();
+    for cell in tl.into_iter() {
+        out.extend(cell.into_inner());
+    }
+    out
+}
+
+fn self_gather<F: Fn(usize, usize) -> bool + Sync>(
+    node: &dyn BVHNode,
+    depth: usize,
+    accept: &F,
+    tl: &ThreadLocal<RefCell<Vec<(usize, usize)>>>,
+) {
+    if node.is_leaf() {
+        let local = tl.get_or(|| RefCell::new(Vec::new()));
+        local.borrow_mut().extend(leaf_node_self_pairs(node).filter(|&(i, j)| accept(i, j)));
+        return;
+    }
+    let (l, r) = node.children();
+    let (l, r) = (l.unwrap(), r.unwrap());
+    if depth < MAX_DEPTH {
+        join(
+            || join(|| self_gather(l, depth + 1, accept, tl), || self_gather(r, depth + 1, accept, tl)),
+            || gather(l, r, depth + 1, LeafPairs::Sorted, accept, tl),
+        );
+    } else {
+        self_gather(l, depth + 1, accept, tl);
+        self_gather(r, depth + 1, accept, tl);
+        gather(l, r, depth + 1, LeafPairs::Sorted, accept, tl);
+    }
+}
+
+fn gather<F: Fn(usize, usize) -> bool + Sync>(
     s1: &dyn BVHNode,
     s2: &dyn BVHNode,
     depth: usize,
+    leaf_pairs: LeafPairs,
+    accept: &F,
     tl: &ThreadLocal<RefCell<Vec<(usize, usize)>>>,
 ) {
     // no intersection → nothing to do
@@ -138,26 +228,136 @@ fn gather(
 
     match (s1.is_leaf(), s2.is_leaf()) {
         (true, true) => {
-            // both leaves: write into *this* thread’s Vec
+            // both leaves: write the pairs whose own boxes overlap into *this* thread’s Vec
             let local = tl.get_or(|| RefCell::new(Vec::new()));
-            for &i in s1.leaf_indices().unwrap() {
-                for &j in s2.leaf_indices().unwrap() {
-                    if i < j {
-                        local.borrow_mut().push((i, j));
-                    }
+            local.borrow_mut().extend(leaf_node_pairs(s1, s2, leaf_pairs).filter(|&(i, j)| accept(i, j)));
+        }
+
+        (true, false) => {
+            let (l, r) = s2.children();
+            gather(s1, l.unwrap(), depth + 1, leaf_pairs, accept, tl);
+            gather(s1, r.unwrap(), depth + 1, leaf_pairs, accept, tl);
+        }
+        (false, true) => {
+            let (l, r) = s1.children();
+            gather(l.unwrap(), s2, depth + 1, leaf_pairs, accept, tl);
+            gather(r.unwrap(), s2, depth + 1, leaf_pairs, accept, tl);
+        }
+
+        (false, false) => {
+            let (s1l, s1r) = s1.children();
+            let (s2l, s2r) = s2.children();
+            let (s1l, s1r) = (s1l.unwrap(), s1r.unwrap());
+            let (s2l, s2r) = (s2l.unwrap(), s2r.unwrap());
+
+            if depth < MAX_DEPTH {
+                join(
+                    || {
+                        gather(s1l, s2l, depth + 1, leaf_pairs, accept, tl);
+                        gather(s1l, s2r, depth + 1, leaf_pairs, accept, tl);
+                    },
+                    || {
+                        gather(s1r, s2l, depth + 1, leaf_pairs, accept, tl);
+                        gather(s1r, s2r, depth + 1, leaf_pairs, accept, tl);
+                    },
+                );
+            } else {
+                // sequential fallback
+                gather(s1l, s2l, depth + 1, leaf_pairs, accept, tl);
+                gather(s1l, s2r, depth + 1, leaf_pairs, accept, tl);
+                gather(s1r, s2l, depth + 1, leaf_pairs, accept, tl);
+                gather(s1r, s2r, depth + 1, leaf_pairs, accept, tl);
+            }
+        }
+    }
+}
+
+// early-exit traversal: runs `test` on candidate pairs as they are found and stops every
+// rayon task once one of them returns true
+pub fn parallel_broad_phase_any<F: Fn(usize, usize) -> bool + Sync>(
+    s1: &dyn BVHNode,
+    s2: &dyn BVHNode,
+    test: &F,
+) -> bool {
+    let found = AtomicBool::new(false);
+    gather_any(s1, s2, 0, LeafPairs::Ordered, test, &found);
+    found.into_inner()
+}
+
+// early-exit counterpart of parallel_self_broad_phase_check
+pub fn parallel_self_broad_phase_any<F: Fn(usize, usize) -> bool + Sync>(
+    node: &dyn BVHNode,
+    test: &F,
+) -> bool {
+    let found = AtomicBool::new(false);
+    self_gather_any(node, 0, test, &found);
+    found.into_inner()
+}
+
+fn self_gather_any<F: Fn(usize, usize) -> bool + Sync>(
+    node: &dyn BVHNode,
+    depth: usize,
+    test: &F,
+    found: &AtomicBool,
+) {
+    if found.load(Ordering::Relaxed) {
+        return;
+    }
+    if node.is_leaf() {
+        if leaf_node_self_pairs(node).any(|(i, j)| test(i, j)) {
+            found.store(true, Ordering::Relaxed);
+        }
+        return;
+    }
+    let (l, r) = node.children();
+    let (l, r) = (l.unwrap(), r.unwrap());
+    if depth < MAX_DEPTH {
+        join(
+            || join(|| self_gather_any(l, depth + 1, test, found), || self_gather_any(r, depth + 1, test, found)),
+            || gather_any(l, r, depth + 1, LeafPairs::Sorted, test, found),
+        );
+    } else {
+        self_gather_any(l, depth + 1, test, found);
+        self_gather_any(r, depth + 1, test, found);
+        gather_any(l, r, depth + 1, LeafPairs::Sorted, test, found);
+    }
+}
+
+fn gather_any<F: Fn(usize, usize) -> bool + Sync>(
+    s1: &dyn BVHNode,
+    s2: &dyn BVHNode,
+    depth: usize,
+    leaf_pairs: LeafPairs,
+    test: &F,
+    found: &AtomicBool,
+) {
+    // another task already found a contact, or no intersection → nothing to do
+    if found.load(Ordering::Relaxed) || !s1.intersects(s2) {
+        return;
+    }
+
+    match (s1.is_leaf(), s2.is_leaf()) {
+        (true, true) => {
+            for (i, j) in leaf_node_pairs(s1, s2, leaf_pairs) {
+                if found.load(Ordering::Relaxed) {
+                    return;
+                }
+                if test(i, j) {
+                    found.store(true, Ordering::Relaxed);
+                    return;
                 }
             }
         }
 
         (true, false) => {
             let (l, r) = s2.children();
-            gather(s1, l.unwrap(), depth + 1, tl);
-            gather(s1, r.unwrap(), depth + 1, tl);
+            gather_any(s1, l.unwrap(), depth + 1, leaf_pairs, test, found);
+            gather_any(s1, r.unwrap(), depth + 1, leaf_pairs, test, found);
         }
         (false, true) => {
             let (l, r) = s1.children();
-            gather(l.unwrap(), s2, depth + 1, tl);
-            gather(r.unwrap(), s2, depth + 1, tl);
+            gather_any(l.unwrap(), s2, depth + 1, leaf_pairs, test, found);
+            gather_any(r.unwrap(), s2, depth + 1, leaf_pairs, test, found);
         }
 
         (false, false) => {
@@ -169,20 +369,20 @@ fn gather(
             if depth < MAX_DEPTH {
                 join(
                     || {
-                        gather(s1l, s2l, depth + 1, tl);
-                        gather(s1l, s2r, depth + 1, tl);
+                        gather_any(s1l, s2l, depth + 1, leaf_pairs, test, found);
+                        gather_any(s1l, s2r, depth + 1, leaf_pairs, test, found);
                     },
                     || {
-                        gather(s1r, s2l, depth + 1, tl);
-                        gather(s1r, s2r, depth + 1, tl);
+                        gather_any(s1r, s2l, depth + 1, leaf_pairs, test, found);
+                        gather_any(s1r, s2r, depth + 1, leaf_pairs, test, found);
                     },
                 );
             } else {
                 // sequential fallback
-                gather(s1l, s2l, depth + 1, tl);
-                gather(s1l, s2r, depth + 1, tl);
-                gather(s1r, s2l, depth + 1, tl);
-                gather(s1r, s2r, depth + 1, tl);
+                gather_any(s1l, s2l, depth + 1, leaf_pairs, test, found);
+                gather_any(s1l, s2r, depth + 1, leaf_pairs, test, found);
+                gather_any(s1r, s2l, depth + 1, leaf_pairs, test, found);
+                gather_any(s1r, s2r, depth + 1, leaf_pairs, test, found);
             }
         }
     }
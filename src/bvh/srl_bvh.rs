@@ -1,7 +1,5 @@
 use apollo_rust_spatial::vectors::V3;
-use super::structs::{AABB, BVHNode, BVHInternalNode, BVHLeafNode};
-use std::collections::HashSet;
-use std::hash::Hash;
+use super::structs::{AABB, BVHNode, BVHInternalNode, BVHLeafNode, BuildStrategy, LeafPairs, leaf_node_pairs, leaf_node_self_pairs};
 
 pub fn serial_longest_extent_axis(aabb_indices: &[usize], all_aabbs:&[AABB])->(usize, f64){
     let mut min_v =  V3::new(f64::INFINITY,  f64::INFINITY,  f64::INFINITY);
@@ -23,7 +21,7 @@ pub fn serial_longest_extent_axis(aabb_indices: &[usize], all_aabbs:&[AABB])->(u
 }
 
 pub fn serial_split_at_axis<'a>(aabb_indices: &'a mut [usize], all_aabbs:&[AABB], axis:usize, midpoint: f64)->(&'a mut [usize], &'a mut [usize]){
-    let mid = aabb_indices.into_iter().partition_in_place(
+    let mid = aabb_indices.iter_mut().partition_in_place(
         |&idx| {
             all_aabbs[idx].center[axis] < midpoint
         }
@@ -31,19 +29,147 @@ pub fn serial_split_at_axis<'a>(aabb_indices: &'a mut [usize], all_aabbs:&[AABB]
     aabb_indices.split_at_mut(mid)
 }
 
+pub const SAH_BINS: usize = 16;
+
+// primitives whose centroid falls in one bin along one axis
+#[derive(Clone, Copy)]
+pub(crate) struct SahBin {
+    min: V3,
+    max: V3,
+    count: usize,
+}
+
+impl SahBin {
+    fn empty() -> Self {
+        Self {
+            min: V3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+            count: 0,
+        }
+    }
+
+    fn add(&mut self, bb: &AABB) {
+        self.min = self.min.inf(&bb.min_coords);
+        self.max = self.max.sup(&bb.max_coords);
+        self.count += 1;
+    }
+
+    fn merge(&self, other: &SahBin) -> SahBin {
+        SahBin { min: self.min.inf(&other.min), max: self.max.sup(&other.max), count: self.count + other.count }
+    }
+
+    fn area(&self) -> f64 {
+        if self.count == 0 { return 0.0; }
+        let e = self.max - self.min;
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+}
+
+// one row of bins per axis
+pub(crate) type SahBins = [[SahBin; SAH_BINS]; 3];
+
+pub(crate) fn empty_sah_bins() -> SahBins {
+    [[SahBin::empty(); SAH_BINS]; 3]
+}
+
+pub(crate) fn merge_sah_bins(a: &SahBins, b: &SahBins) -> SahBins {
+    let mut out = *a;
+    for axis in 0..3 {
+        for k in 0..SAH_BINS {
+            out[axis][k] = a[axis][k].merge(&b[axis][k]);
+        }
+    }
+    out
+}
+
+pub(crate) fn sah_bin_of(c: f64, c_min: f64, c_extent: f64) -> usize {
+    if c_extent <= 0.0 { return 0; }
+    (((c - c_min) / c_extent * SAH_BINS as f64) as usize).min(SAH_BINS - 1)
+}
+
+pub(crate) fn add_to_sah_bins(bins: &mut SahBins, bb: &AABB, c_min: &V3, c_max: &V3) {
+    for axis in 0..3 {
+        let k = sah_bin_of(bb.center[axis], c_min[axis], c_max[axis] - c_min[axis]);
+        bins[axis][k].add(bb);
+    }
+}
+
+// sweeps every bin boundary of every axis and keeps the one minimizing
+// area(left) * count(left) + area(right) * count(right).
+// None when all centroids coincide and no boundary separates them.
+pub(crate) fn best_sah_split(bins: &SahBins, c_min: &V3, c_max: &V3) -> Option<(usize, f64)> {
+    let mut best: Option<(usize, f64, f64)> = None;
+    for axis in 0..3 {
+        let extent = c_max[axis] - c_min[axis];
+        if extent <= 0.0 { continue; }
+        // right_cost[k] is the cost of bins k.. on the right side
+        let mut right_cost = [0.0; SAH_BINS];
+        let mut acc = SahBin::empty();
+        for k in (1..SAH_BINS).rev() {
+            acc = acc.merge(&bins[axis][k]);
+            right_cost[k] = acc.area() * acc.count as f64;
+        }
+        let mut left = SahBin::empty();
+        let mut right_count = bins[axis].iter().map(|b| b.count).sum::<usize>();
+        for k in 1..SAH_BINS {
+            left = left.merge(&bins[axis][k - 1]);
+            right_count -= bins[axis][k - 1].count;
+            if left.count == 0 || right_count == 0 { continue; }
+            let cost = left.area() * left.count as f64 + right_cost[k];
+            if best.is_none_or(|(_, _, c)| cost < c) {
+                best = Some((axis, c_min[axis] + extent * k as f64 / SAH_BINS as f64, cost));
+            }
+        }
+    }
+    best.map(|(axis, position, _)| (axis, position))
+}
+
+fn serial_centroid_bounds(aabb_indices: &[usize], all_aabbs: &[AABB]) -> (V3, V3) {
+    let mut min_v = V3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max_v = V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for &i in aabb_indices {
+        min_v = min_v.inf(&all_aabbs[i].center);
+        max_v = max_v.sup(&all_aabbs[i].center);
+    }
+    (min_v, max_v)
+}
+
+// axis and split position chosen by the binned surface area heuristic
+pub fn serial_sah_axis(aabb_indices: &[usize], all_aabbs: &[AABB]) -> Option<(usize, f64)> {
+    let (c_min, c_max) = serial_centroid_bounds(aabb_indices, all_aabbs);
+    let mut bins = empty_sah_bins();
+    for &i in aabb_indices {
+        add_to_sah_bins(&mut bins, &all_aabbs[i], &c_min, &c_max);
+    }
+    best_sah_split(&bins, &c_min, &c_max)
+}
+
 pub fn serial_build_bvh(aabb_indices: &mut [usize], all_aabbs:&[AABB], cut_off_size:usize)->Box<dyn BVHNode>{
+    serial_build_bvh_with(aabb_indices, all_aabbs, cut_off_size, BuildStrategy::Midpoint)
+}
+
+// picks the split for strategy and partitions aabb_indices around it.
+// None when no split separates the primitives, e.g. when all bounding boxes are equal.
+pub fn serial_split_at_axis_with<'a>(aabb_indices: &'a mut [usize], all_aabbs:&[AABB], strategy: BuildStrategy)->Option<(&'a mut [usize], &'a mut [usize])>{
+    let (axis, midpoint) = match strategy {
+        BuildStrategy::Midpoint => Some(serial_longest_extent_axis(aabb_indices, all_aabbs)),
+        BuildStrategy::Sah => serial_sah_axis(aabb_indices, all_aabbs),
+    }?;
+    let (left, right) = serial_split_at_axis(aabb_indices, all_aabbs, axis, midpoint);
+    (!left.is_empty() && !right.is_empty()).then_some((left, right))
+}
+
+pub fn serial_build_bvh_with(aabb_indices: &mut [usize], all_aabbs:&[AABB], cut_off_size:usize, strategy: BuildStrategy)->Box<dyn BVHNode>{
     if aabb_indices.len() <= cut_off_size{
         return Box::new(BVHLeafNode::new( aabb_indices.to_vec(), all_aabbs));
     }
-    let (axis,midpoint) = serial_longest_extent_axis(aabb_indices, all_aabbs);
-    let (indices_left, indices_right) = serial_split_at_axis(aabb_indices, all_aabbs, axis, midpoint);
     // safeguard for degenerate cases where all bounding boxes equal
-    if indices_left.is_empty() || indices_right.is_empty() {
+    let Some((indices_left, indices_right)) = serial_split_at_axis_with(aabb_indices, all_aabbs, strategy) else {
         return Box::new(BVHLeafNode::new(aabb_indices.to_vec(), all_aabbs));
-    }
+    };
     // do recursive calls
-    let left_tree = serial_build_bvh(indices_left, all_aabbs, cut_off_size);
-    let right_tree = serial_build_bvh(indices_right, all_aabbs, cut_off_size);
+    let left_tree = serial_build_bvh_with(indices_left, all_aabbs, cut_off_size, strategy);
+    let right_tree = serial_build_bvh_with(indices_right, all_aabbs, cut_off_size, strategy);
     let aabb = left_tree.union_aabb(&*right_tree);
     Box::new(BVHInternalNode::new(aabb, left_tree, right_tree))
 }
@@ -51,6 +177,39 @@ pub fn serial_build_bvh(aabb_indices: &mut [usize], all_aabbs:&[AABB], cut_off_s
 pub fn serial_broad_phase_check(
     s1: &dyn BVHNode,
     s2: &dyn BVHNode,
+)->Vec<(usize, usize)> {
+    serial_pairs(s1, s2, LeafPairs::Ordered, &|_, _| true)
+}
+
+// pairs between trees built over two different shape sets, i indexes the set of s1 and j the set of s2
+pub fn serial_bipartite_broad_phase_check(s1: &dyn BVHNode, s2: &dyn BVHNode) -> Vec<(usize, usize)> {
+    serial_pairs(s1, s2, LeafPairs::All, &|_, _| true)
+}
+
+// pairs of a tree with itself: (L, L), (R, R) and (L, R) are each visited once instead of
+// traversing the tree against itself and discarding the mirrored half of the pairs
+pub fn serial_self_broad_phase_check(node: &dyn BVHNode) -> Vec<(usize, usize)> {
+    serial_self_broad_phase_check_filtered(node, &|_, _| true)
+}
+
+// same as serial_self_broad_phase_check, but a pair is only emitted if accept(i, j) holds
+pub fn serial_self_broad_phase_check_filtered<F: Fn(usize, usize) -> bool>(node: &dyn BVHNode, accept: &F) -> Vec<(usize, usize)> {
+    if node.is_leaf() {
+        return leaf_node_self_pairs(node).filter(|&(i, j)| accept(i, j)).collect();
+    }
+    let (l, r) = node.children();
+    let (l, r) = (l.unwrap(), r.unwrap());
+    let mut v = serial_self_broad_phase_check_filtered(l, accept);
+    v.extend(serial_self_broad_phase_check_filtered(r, accept));
+    v.extend(serial_pairs(l, r, LeafPairs::Sorted, accept));
+    v
+}
+
+fn serial_pairs<F: Fn(usize, usize) -> bool>(
+    s1: &dyn BVHNode,
+    s2: &dyn BVHNode,
+    leaf_pairs: LeafPairs,
+    accept: &F,
 )->Vec<(usize, usize)> {
     // No intersection ⇒ no contacts
     if !s1.intersects(s2) {
@@ -58,48 +217,90 @@ pub fn serial_broad_phase_check(
     }
 
     match (s1.is_leaf(), s2.is_leaf()) {
-        // both leaves ⇒ collect leaf–leaf pairs
-        (true, true) => {
-            let is1 = s1.leaf_indices().unwrap();
-            let is2 = s2.leaf_indices().unwrap();
-            let mut out = Vec::with_capacity(is1.len() * is2.len());
-            for &i in is1 {
-                for &j in is2 {
-                    if i < j {
-                        out.push((i, j));
-                    }
-                }
-            }
-            out
-        }
+        // both leaves ⇒ collect leaf–leaf pairs whose own boxes overlap
+        (true, true) => leaf_node_pairs(s1, s2, leaf_pairs).filter(|&(i, j)| accept(i, j)).collect(),
 
         // one side is leaf ⇒ recurse on the other side and concatenate
         (true, false) => {
             let (l, r) = s2.children();
-            let mut left  =serial_broad_phase_check(s1, l.unwrap());
-            let right = serial_broad_phase_check(s1, r.unwrap());
+            let mut left  =serial_pairs(s1, l.unwrap(), leaf_pairs, accept);
+            let right = serial_pairs(s1, r.unwrap(), leaf_pairs, accept);
             left.extend(right);
             left
         }
         (false, true) => {
             let (l, r) = s1.children();
-            let mut left  = serial_broad_phase_check(l.unwrap(), s2);
-            let right = serial_broad_phase_check(r.unwrap(), s2);
+            let mut left  = serial_pairs(l.unwrap(), s2, leaf_pairs, accept);
+            let right = serial_pairs(r.unwrap(), s2, leaf_pairs, accept);
             left.extend(right);
             left
         }
 
-        // both internal ⇒ spawn two tasks and merge their results
+        // both internal ⇒ visit the four child pairs and merge their results
         (false, false) => {
             let (s1l, s1r) = s1.children();
             let (s2l, s2r) = s2.children();
                 let mut v = Vec::new();
-                v.extend(serial_broad_phase_check(s1l.unwrap(), s2l.unwrap(), ));
-                v.extend(serial_broad_phase_check(s1l.unwrap(), s2r.unwrap(),));
-                v.extend(serial_broad_phase_check(s1r.unwrap(), s2l.unwrap(),));
-                v.extend(serial_broad_phase_check(s1r.unwrap(), s2r.unwrap(),));
+                v.extend(serial_pairs(s1l.unwrap(), s2l.unwrap(), leaf_pairs, accept));
+                v.extend(serial_pairs(s1l.unwrap(), s2r.unwrap(), leaf_pairs, accept));
+                v.extend(serial_pairs(s1r.unwrap(), s2l.unwrap(), leaf_pairs, accept));
+                v.extend(serial_pairs(s1r.unwrap(), s2r.unwrap(), leaf_pairs, accept));
                 v
             
         }
     }
-}
\ No newline at end of file
+}
+
+// early-exit traversal: returns as soon as `test` accepts a candidate pair
+pub fn serial_broad_phase_any<F: Fn(usize, usize) -> bool>(
+    s1: &dyn BVHNode,
+    s2: &dyn BVHNode,
+    test: &F,
+) -> bool {
+    serial_any(s1, s2, LeafPairs::Ordered, test)
+}
+
+// early-exit counterpart of serial_self_broad_phase_check
+pub fn serial_self_broad_phase_any<F: Fn(usize, usize) -> bool>(node: &dyn BVHNode, test: &F) -> bool {
+    if node.is_leaf() {
+        return leaf_node_self_pairs(node).any(|(i, j)| test(i, j));
+    }
+    let (l, r) = node.children();
+    let (l, r) = (l.unwrap(), r.unwrap());
+    serial_self_broad_phase_any(l, test)
+        || serial_self_broad_phase_any(r, test)
+        || serial_any(l, r, LeafPairs::Sorted, test)
+}
+
+fn serial_any<F: Fn(usize, usize) -> bool>(
+    s1: &dyn BVHNode,
+    s2: &dyn BVHNode,
+    leaf_pairs: LeafPairs,
+    test: &F,
+) -> bool {
+    if !s1.intersects(s2) {
+        return false;
+    }
+
+    match (s1.is_leaf(), s2.is_leaf()) {
+        (true, true) => leaf_node_pairs(s1, s2, leaf_pairs).any(|(i, j)| test(i, j)),
+        (true, false) => {
+            let (l, r) = s2.children();
+            serial_any(s1, l.unwrap(), leaf_pairs, test) || serial_any(s1, r.unwrap(), leaf_pairs, test)
+        }
+        (false, true) => {
+            let (l, r) = s1.children();
+            serial_any(l.unwrap(), s2, leaf_pairs, test) || serial_any(r.unwrap(), s2, leaf_pairs, test)
+        }
+        (false, false) => {
+            let (s1l, s1r) = s1.children();
+            let (s2l, s2r) = s2.children();
+            let (s1l, s1r) = (s1l.unwrap(), s1r.unwrap());
+            let (s2l, s2r) = (s2l.unwrap(), s2r.unwrap());
+            serial_any(s1l, s2l, leaf_pairs, test)
+                || serial_any(s1l, s2r, leaf_pairs, test)
+                || serial_any(s1r, s2l, leaf_pairs, test)
+                || serial_any(s1r, s2r, leaf_pairs, test)
+        }
+    }
+}
@@ -1,4 +1,6 @@
 use apollo_rust_spatial::vectors::V3;
+use rayon::join;
+use crate::bvh::par_bvh::parallel_build_bvh;
 
 #[derive(Debug, Clone, Copy)]
 pub struct AABB{
@@ -9,8 +11,10 @@ pub struct AABB{
 
 impl AABB {
     pub fn new(min_coords: V3, max_coords:V3)->Self {
+        // the box of no primitives, (+inf, -inf), is allowed: it intersects nothing
         debug_assert!(
-            (0..3).all(|i| min_coords[i] <= max_coords[i]),
+            (0..3).all(|i| min_coords[i] <= max_coords[i])
+                || (min_coords.min() == f64::INFINITY && max_coords.max() == f64::NEG_INFINITY),
             "Invalid AABB: min > max"
         );
         Self { min_coords, max_coords, center:0.5*(min_coords + max_coords) }
@@ -32,6 +36,22 @@ impl AABB {
         true
     }
 
+    // grows the box by margin on every side, so boxes within margin of each other intersect
+    pub fn inflated(&self, margin: f64) -> AABB {
+        AABB::new(self.min_coords.add_scalar(-margin), self.max_coords.add_scalar(margin))
+    }
+
+    // euclidean gap between the two boxes, 0 when they intersect
+    pub fn distance(&self, other: &AABB) -> f64 {
+        let gap = (other.min_coords - self.max_coords).sup(&(self.min_coords - other.max_coords)).sup(&V3::zeros());
+        gap.norm()
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let e = self.max_coords - self.min_coords;
+        2.0 * (e.x * e.y + e.y * e.z + e.z * e.x)
+    }
+
     pub fn union(&self, other: &AABB) -> AABB {
         let min = self.min_coords.inf(&other.min_coords);
         let max = self.max_coords.sup(&other.max_coords);
@@ -39,6 +59,60 @@ impl AABB {
     }
 }
 
+// how a node's primitives are divided between its two children
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BuildStrategy {
+    // spatial midpoint of the longest axis, cheap but poor on clustered or elongated scenes
+    #[default]
+    Midpoint,
+    // binned surface area heuristic, slower to build but gives tighter, less overlapping nodes
+    Sah,
+}
+
+// which index pairs a leaf-leaf visit emits
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum LeafPairs {
+    Ordered, // only pairs with i < j, for a tree traversed against itself
+    Sorted, // every pair as (min, max), for two disjoint subtrees of the same tree
+    All, // every (i, j) as is, for trees over two different index spaces
+}
+
+impl LeafPairs {
+    pub(crate) fn emit(&self, i: usize, j: usize) -> Option<(usize, usize)> {
+        match self {
+            LeafPairs::Ordered => (i < j).then_some((i, j)),
+            LeafPairs::Sorted => Some((i.min(j), i.max(j))),
+            LeafPairs::All => Some((i, j)),
+        }
+    }
+}
+
+// candidate pairs between two leaves, keeping only those whose own boxes overlap
+pub(crate) fn cross_leaf_pairs<'a>(is1: &'a [usize], bbs1: &'a [AABB], is2: &'a [usize], bbs2: &'a [AABB],
+                                   leaf_pairs: LeafPairs) -> impl Iterator<Item = (usize, usize)> + 'a {
+    is1.iter().zip(bbs1).flat_map(move |(&i, b1)| {
+        is2.iter().zip(bbs2).filter_map(move |(&j, b2)| leaf_pairs.emit(i, j).filter(|_| b1.intersects(b2)))
+    })
+}
+
+// every pair of distinct, overlapping primitives of one leaf, as (min, max)
+pub(crate) fn within_leaf_pairs<'a>(indices: &'a [usize], aabbs: &'a [AABB]) -> impl Iterator<Item = (usize, usize)> + 'a {
+    indices.iter().zip(aabbs).enumerate().flat_map(move |(a, (&i, b1))| {
+        indices[a + 1..].iter().zip(&aabbs[a + 1..])
+            .filter(move |(_, b2)| b1.intersects(b2))
+            .map(move |(&j, _)| (i.min(j), i.max(j)))
+    })
+}
+
+// cross_leaf_pairs / within_leaf_pairs on leaf nodes
+pub(crate) fn leaf_node_pairs<'a>(s1: &'a dyn BVHNode, s2: &'a dyn BVHNode, leaf_pairs: LeafPairs) -> impl Iterator<Item = (usize, usize)> + 'a {
+    cross_leaf_pairs(s1.leaf_indices().unwrap(), s1.leaf_aabbs().unwrap(), s2.leaf_indices().unwrap(), s2.leaf_aabbs().unwrap(), leaf_pairs)
+}
+
+pub(crate) fn leaf_node_self_pairs(node: &dyn BVHNode) -> impl Iterator<Item = (usize, usize)> + '_ {
+    within_leaf_pairs(node.leaf_indices().unwrap(), node.leaf_aabbs().unwrap())
+}
+
 pub trait BVHNode: Send + Sync {
     fn is_leaf(&self) -> bool;
 
@@ -46,6 +120,9 @@ pub trait BVHNode: Send + Sync {
 
     fn leaf_indices(&self) -> Option<&[usize]>;
 
+    // boxes of the primitives of a leaf, in the same order as leaf_indices
+    fn leaf_aabbs(&self) -> Option<&[AABB]>;
+
     fn aabb_ref(&self)->&AABB;
 
     fn union_aabb(&self, other: &dyn BVHNode) -> AABB{
@@ -55,17 +132,41 @@ pub trait BVHNode: Send + Sync {
     fn intersects(&self, other: &dyn BVHNode) -> bool {
         self.aabb_ref().intersects(other.aabb_ref())
     }
+
+    // recomputes the bounds bottom-up from all_aabbs, rebuilding any child subtree whose box grew
+    // by more than max_growth (in surface area) since it was built. returns whether this node degraded itself.
+    // depth is the node's depth below the node refit was called on, 0 there.
+    fn refit(&mut self, all_aabbs: &[AABB], cut_off_size: usize, max_growth: f64, depth: usize) -> bool;
+
+    fn collect_indices(&self, out: &mut Vec<usize>);
+}
+
+// rebuilds the subtree below node from scratch over the same primitives
+pub fn rebuild_subtree(node: &dyn BVHNode, all_aabbs: &[AABB], cut_off_size: usize) -> Box<dyn BVHNode> {
+    let mut indices = Vec::new();
+    node.collect_indices(&mut indices);
+    parallel_build_bvh(&mut indices, all_aabbs, cut_off_size)
 }
 
+// refit forks down to this depth (up to 2^depth tasks) and walks the subtrees below it serially,
+// refitting a node is too little work to pay for a fork
+const REFIT_PARALLEL_DEPTH: usize = 6;
+
+// a flat or point-like group of primitives is built with a box of zero area, any movement at all
+// would count as unbounded growth and rebuild it on every refit
+const MIN_BUILD_AREA: f64 = 1e-12;
+
 pub struct BVHInternalNode{
     pub aabb: AABB,
     pub left: Box<dyn BVHNode>,
     pub right: Box<dyn BVHNode>,
+    pub build_area: f64, // surface area of the box when the node was built, used to detect degradation
 }
 
 impl BVHInternalNode{
     pub fn new(aabb: AABB,  left: Box<dyn BVHNode>, right:  Box<dyn BVHNode>)->Self{
         Self{
+            build_area: aabb.surface_area(),
             aabb,
             left,
             right
@@ -76,6 +177,7 @@ impl BVHInternalNode{
 pub struct BVHLeafNode{
     pub aabb: AABB,
     pub shape_indices: Vec<usize>,
+    pub shape_aabbs: Vec<AABB>, // copies of all_aabbs[shape_indices], to filter leaf-leaf pairs
 }
 
 impl BVHLeafNode{
@@ -87,6 +189,7 @@ impl BVHLeafNode{
             max_coords = max_coords.sup(&(all_aabbs[i].max_coords));
         }
         Self{
+            shape_aabbs: shape_indices.iter().map(|&i| all_aabbs[i]).collect(),
             shape_indices,
             aabb:  AABB::new(min_coords, max_coords),
         }
@@ -104,9 +207,38 @@ impl BVHNode for BVHInternalNode{
         None
     }
 
+    fn leaf_aabbs(&self) -> Option<&[AABB]> {
+        None
+    }
+
     fn aabb_ref(&self) -> &AABB {
         &self.aabb
     }
+
+    fn refit(&mut self, all_aabbs: &[AABB], cut_off_size: usize, max_growth: f64, depth: usize) -> bool {
+        let (left, right) = (&mut self.left, &mut self.right);
+        let (left_degraded, right_degraded) = if depth < REFIT_PARALLEL_DEPTH {
+            join(
+                || left.refit(all_aabbs, cut_off_size, max_growth, depth + 1),
+                || right.refit(all_aabbs, cut_off_size, max_growth, depth + 1),
+            )
+        } else {
+            (left.refit(all_aabbs, cut_off_size, max_growth, depth + 1), right.refit(all_aabbs, cut_off_size, max_growth, depth + 1))
+        };
+        if left_degraded {
+            self.left = rebuild_subtree(&*self.left, all_aabbs, cut_off_size);
+        }
+        if right_degraded {
+            self.right = rebuild_subtree(&*self.right, all_aabbs, cut_off_size);
+        }
+        self.aabb = self.left.union_aabb(&*self.right);
+        self.aabb.surface_area() > max_growth * self.build_area.max(MIN_BUILD_AREA)
+    }
+
+    fn collect_indices(&self, out: &mut Vec<usize>) {
+        self.left.collect_indices(out);
+        self.right.collect_indices(out);
+    }
 }
 
 impl BVHNode for BVHLeafNode{
@@ -120,7 +252,27 @@ impl BVHNode for BVHLeafNode{
         Some(self.shape_indices.as_slice())
     }
 
+    fn leaf_aabbs(&self) -> Option<&[AABB]> {
+        Some(self.shape_aabbs.as_slice())
+    }
+
     fn aabb_ref(&self) -> &AABB {
         &self.aabb
     }
+    // a leaf cannot be improved by rebuilding, it only needs its bounds refreshed
+    fn refit(&mut self, all_aabbs: &[AABB], _cut_off_size: usize, _max_growth: f64, _depth: usize) -> bool {
+        let mut min_coords = V3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max_coords = V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for (k, &i) in self.shape_indices.iter().enumerate(){
+            self.shape_aabbs[k] = all_aabbs[i];
+            min_coords = min_coords.inf(&(all_aabbs[i].min_coords));
+            max_coords = max_coords.sup(&(all_aabbs[i].max_coords));
+        }
+        self.aabb = AABB::new(min_coords, max_coords);
+        false
+    }
+
+    fn collect_indices(&self, out: &mut Vec<usize>) {
+        out.extend_from_slice(&self.shape_indices);
+    }
 }
@@ -0,0 +1,58 @@
+// collision groups: an object belongs to the groups set in `group` and only collides with
+// objects whose group intersects its `mask`. both sides must agree for a pair to be tested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CollisionFilter {
+    pub group: u32,
+    pub mask: u32,
+}
+
+impl CollisionFilter {
+    // member of every group, collides with everything
+    pub const ALL: CollisionFilter = CollisionFilter { group: u32::MAX, mask: u32::MAX };
+
+    pub fn new(group: u32, mask: u32) -> Self {
+        Self { group, mask }
+    }
+
+    pub fn interacts_with(&self, other: &CollisionFilter) -> bool {
+        self.group & other.mask != 0 && other.group & self.mask != 0
+    }
+}
+
+impl Default for CollisionFilter {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
+// decides which pairs (i, j) of a shape set are worth testing: the group/mask check of
+// filters[i] and filters[j], then the optional user predicate (e.g. adjacent robot links)
+#[derive(Clone, Copy)]
+pub struct PairFilter<'a> {
+    filters: &'a [CollisionFilter],
+    predicate: Option<&'a (dyn Fn(usize, usize) -> bool + Sync)>,
+}
+
+impl<'a> PairFilter<'a> {
+    pub fn new(filters: &'a [CollisionFilter]) -> Self {
+        Self { filters, predicate: None }
+    }
+
+    // predicate(i, j) returning false drops the pair, it is called with i < j
+    pub fn with_predicate(self, predicate: &'a (dyn Fn(usize, usize) -> bool + Sync)) -> Self {
+        Self { predicate: Some(predicate), ..self }
+    }
+
+    pub fn len(&self) -> usize {
+        self.filters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn accepts(&self, i: usize, j: usize) -> bool {
+        self.filters[i].interacts_with(&self.filters[j])
+            && self.predicate.is_none_or(|p| p(i, j))
+    }
+}
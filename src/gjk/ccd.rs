@@ -0,0 +1,150 @@
+use std::ops::Sub;
+use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
+use apollo_rust_spatial::vectors::V3;
+use rayon::prelude::*;
+use crate::gjk::gjk::{Contact, QueryOptions};
+use crate::shape::shape::ShapeTrait;
+
+const _TOI_TOL: f64 = 1e-6;
+const _TOI_MAX_ITERS: usize = 100;
+
+// pose at time t in [0, 1] of a motion from start to end with constant velocities:
+// the translation is interpolated linearly and the rotation along the shortest arc
+pub fn interpolate_pose(start: &LieGroupISE3q, end: &LieGroupISE3q, t: f64) -> LieGroupISE3q {
+    LieGroupISE3q::new(start.0.lerp_slerp(&end.0, t))
+}
+
+// farthest distance of a point of the shape from its origin, and the angle the motion rotates it by
+fn radius_and_angle<S: ShapeTrait>(shape: &S, start: &LieGroupISE3q, end: &LieGroupISE3q) -> (f64, f64) {
+    let origin = &start.0.translation.vector;
+    let (min, max) = shape.aabb(start);
+    let radius = min.sub(origin).abs().sup(&max.sub(origin).abs()).norm();
+    (radius, start.0.rotation.rotation_to(&end.0.rotation).angle())
+}
+
+// linear velocity of the shape origin over the motion, and an upper bound on the speed a point
+// of the shape gains from the rotation (angular speed times the farthest point from the origin)
+fn motion_bounds<S: ShapeTrait>(shape: &S, start: &LieGroupISE3q, end: &LieGroupISE3q) -> (V3, f64) {
+    let (radius, angle) = radius_and_angle(shape, start, end);
+    (end.0.translation.vector.sub(&start.0.translation.vector), angle * radius)
+}
+
+// box enclosing the shape over the whole motion. a point sits at origin(t) + offset(t): the origin
+// moves along a segment, and the offset turns along a circular arc of angle <= pi, which strays from
+// the chord between its end offsets by at most the sagitta radius * (1 - cos(angle / 2)). the box of
+// the segment plus the union of the offset boxes at both ends, grown by the sagitta, covers both.
+pub fn swept_aabb<S: ShapeTrait>(shape: &S, start: &LieGroupISE3q, end: &LieGroupISE3q) -> (V3, V3) {
+    let (o1, o2) = (&start.0.translation.vector, &end.0.translation.vector);
+    let (min1, max1) = shape.aabb(start);
+    let (min2, max2) = shape.aabb(end);
+    let (radius, angle) = radius_and_angle(shape, start, end);
+    let sagitta = V3::repeat(radius * (1.0 - (0.5 * angle).cos()));
+    let min = o1.inf(o2) + min1.sub(o1).inf(&min2.sub(o2)) - sagitta;
+    let max = o1.sup(o2) + max1.sub(o1).sup(&max2.sub(o2)) + sagitta;
+    (min, max)
+}
+
+// first time t in [0, 1] at which the shapes touch while moving from their start to their end
+// poses, None if they stay apart over the whole motion. conservative advancement: the gap along the
+// current contact normal cannot close faster than the bound on the relative speed, so stepping by
+// distance / bound never skips over a contact. Some(0.0) if the shapes already overlap at the start.
+pub fn time_of_impact<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, start1: &LieGroupISE3q, end1: &LieGroupISE3q,
+                                                     shape2: &S2, start2: &LieGroupISE3q, end2: &LieGroupISE3q) -> Option<f64> {
+    time_of_impact_with(shape1, start1, end1, shape2, start2, end2, &QueryOptions::default())
+}
+
+// the shapes count as touching once they come within options.contact_threshold of each other
+pub fn time_of_impact_with<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, start1: &LieGroupISE3q, end1: &LieGroupISE3q,
+                                                          shape2: &S2, start2: &LieGroupISE3q, end2: &LieGroupISE3q,
+                                                          options: &QueryOptions) -> Option<f64> {
+    let (v1, w1) = motion_bounds(shape1, start1, end1);
+    let (v2, w2) = motion_bounds(shape2, start2, end2);
+    let relative = v1.sub(&v2);
+    // the normal of a compound contact only separates its nearest part pair, another pair may close
+    // along any direction, so only the full relative speed bounds how fast the gap shrinks
+    let compound = shape1.as_compound().is_some() || shape2.as_compound().is_some();
+    let mut t = 0.0;
+    for _ in 0.._TOI_MAX_ITERS {
+        let contact = Contact::with_options(0, 1, shape1, &interpolate_pose(start1, end1, t),
+                                            shape2, &interpolate_pose(start2, end2, t), options);
+        let gap = contact.distance - options.contact_threshold;
+        // an unconverged distance may overestimate the gap, stop here rather than risk stepping past a contact
+        if gap < _TOI_TOL || !contact.converged {
+            return Some(t);
+        }
+        let linear = if compound { relative.norm() } else { relative.dot(&contact.normal) };
+        let closing = linear + w1 + w2;
+        if closing <= 0.0 {
+            return None;
+        }
+        t += gap / closing;
+        if t > 1.0 {
+            return None;
+        }
+    }
+    // out of iterations while grazing, t is still a lower bound on the time of impact
+    Some(t)
+}
+
+// a pair of shapes that collides during the motion, toi is the first time of contact in [0, 1]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeOfImpact {
+    pub i: usize,
+    pub j: usize,
+    pub toi: f64,
+}
+
+// time of impact for every candidate pair, keeping the ones that touch during the motion
+pub fn serial_narrow_phase_time_of_impact<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    starts:  &[LieGroupISE3q],
+    ends:    &[LieGroupISE3q],
+)-> Vec<TimeOfImpact>{
+    serial_narrow_phase_time_of_impact_with(pairs, shapes, starts, ends, &QueryOptions::default())
+}
+
+pub fn serial_narrow_phase_time_of_impact_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    starts:  &[LieGroupISE3q],
+    ends:    &[LieGroupISE3q],
+    options: &QueryOptions,
+)-> Vec<TimeOfImpact>{
+    assert_eq!(shapes.len(), starts.len(),
+               "shapes and poses slices must have the same length");
+    assert_eq!(shapes.len(), ends.len(),
+               "shapes and poses slices must have the same length");
+    pairs.iter().filter_map(
+        |&(i, j)
+        | time_of_impact_with(&shapes[i], &starts[i], &ends[i], &shapes[j], &starts[j], &ends[j], options)
+            .map(|toi| TimeOfImpact{i, j, toi})
+    ).collect()
+}
+
+pub fn parallel_narrow_phase_time_of_impact<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    starts:  &[LieGroupISE3q],
+    ends:    &[LieGroupISE3q],
+)-> Vec<TimeOfImpact>{
+    parallel_narrow_phase_time_of_impact_with(pairs, shapes, starts, ends, &QueryOptions::default())
+}
+
+pub fn parallel_narrow_phase_time_of_impact_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    starts:  &[LieGroupISE3q],
+    ends:    &[LieGroupISE3q],
+    options: &QueryOptions,
+)-> Vec<TimeOfImpact>{
+    assert_eq!(shapes.len(), starts.len(),
+               "shapes and poses slices must have the same length");
+    assert_eq!(shapes.len(), ends.len(),
+               "shapes and poses slices must have the same length");
+    pairs.par_iter().filter_map(
+        |&(i, j)
+        | time_of_impact_with(&shapes[i], &starts[i], &ends[i], &shapes[j], &starts[j], &ends[j], options)
+            .map(|toi| TimeOfImpact{i, j, toi})
+    ).collect()
+}
@@ -0,0 +1,194 @@
+use apollo_rust_spatial::vectors::V3;
+use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
+use crate::gjk::gjk::{barycentric, SupportPoint, ThreeSimplex};
+use crate::shape::shape::ShapeTrait;
+
+const _EPA_MAX_ITERS: usize = 128;
+
+// result of EPA for a pair of overlapping shapes, everything in world frame.
+// translating shape 2 by depth*normal (or shape 1 by -depth*normal) separates the pair.
+#[derive(Debug, Clone, Copy)]
+pub struct Penetration {
+    pub depth: f64,
+    pub normal: V3, // unit vector pointing from shape 1 towards shape 2
+    pub p1: V3, // deepest point of shape 1 inside shape 2
+    pub p2: V3, // deepest point of shape 2 inside shape 1
+}
+
+#[derive(Clone, Copy)]
+struct PolytopeFace {
+    pub vertices: [usize; 3], // counter-clockwise seen from outside
+    pub normal: V3, // outward unit normal
+    pub d: f64, // distance from the face plane to origin
+}
+
+impl PolytopeFace {
+    // a face whose double area is within tol times its longest edge is a sliver, the same test GJK
+    // uses for collinear triangles
+    pub fn new(vertices: [usize; 3], polytope: &[SupportPoint], tol: f64) -> Self {
+        let (a, b, c) = (polytope[vertices[0]].w, polytope[vertices[1]].w, polytope[vertices[2]].w);
+        let n = (b - a).cross(&(c - a));
+        let size2 = (b - a).norm_squared().max((c - a).norm_squared()).max((c - b).norm_squared());
+        // a sliver face has no usable normal, keep it out of the way of the search
+        if n.norm_squared() <= tol * tol * size2 {
+            return Self { vertices, normal: V3::zeros(), d: f64::INFINITY };
+        }
+        let normal = n.normalize();
+        Self { vertices, normal, d: normal.dot(&a) }
+    }
+
+    // orientation test on the vertices themselves, a sliver face has no normal but still a side
+    pub fn sees(&self, point: &V3, polytope: &[SupportPoint]) -> bool {
+        let (a, b, c) = (polytope[self.vertices[0]].w, polytope[self.vertices[1]].w, polytope[self.vertices[2]].w);
+        (b - a).cross(&(c - a)).dot(&(point - a)) > 0.0
+    }
+
+    // the point is within tol of the face plane and on its side of the edge (a, b): the face stitched
+    // from that edge to the point would lie on top of this one, inside out
+    pub fn folds_over(&self, a: &V3, b: &V3, point: &V3, tol: f64) -> bool {
+        self.d.is_finite() && self.normal.dot(&(point - a)) > -tol && (b - a).cross(&(point - a)).dot(&self.normal) < 0.0
+    }
+}
+
+// the horizon edges chain into one closed loop through all of them
+fn is_simple_loop(horizon: &[(usize, usize)]) -> bool {
+    let mut vertex = horizon[0].1;
+    for _ in 1..horizon.len() {
+        let mut next = horizon.iter().filter(|&&(a, _)| a == vertex);
+        match (next.next(), next.next()) {
+            (Some(&(_, b)), None) => vertex = b,
+            _ => return false,
+        }
+    }
+    vertex == horizon[0].0
+}
+
+// expanding polytope algorithm, seeded with the tetrahedron GJK terminated on. tol is the GJK
+// tolerance, a length. dir is the direction GJK started from (pointing from shape 2 towards shape 1):
+// if every face of the polytope is a sliver there is no depth to measure, and the shapes are
+// reported as touching along it.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn epa<S1: ShapeTrait, S2: ShapeTrait>(simplex: &ThreeSimplex, shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2: &LieGroupISE3q,
+                                                  dir: &V3, tol: f64) -> Penetration {
+    debug_assert_eq!(simplex.len(), 4, "EPA needs a tetrahedron enclosing the origin");
+    let mut polytope: Vec<SupportPoint> = simplex.arr.to_vec();
+    let mut hints = simplex.hints;
+    let centroid = polytope.iter().fold(V3::zeros(), |acc, s| acc + s.w) / 4.0;
+    let mut faces: Vec<PolytopeFace> = [[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]]
+        .iter()
+        .map(|&[a, b, c]| {
+            // orient every face of the initial tetrahedron outwards
+            let f = PolytopeFace::new([a, b, c], &polytope, tol);
+            if f.sees(&centroid, &polytope) { PolytopeFace::new([a, c, b], &polytope, tol) } else { f }
+        })
+        .collect();
+
+    let closest_face = |faces: &[PolytopeFace]| (0..faces.len()).min_by(|&a, &b| faces[a].d.total_cmp(&faces[b].d)).unwrap();
+    let mut closest_index = closest_face(&faces);
+    let mut closest = faces[closest_index];
+    let (mut seen, mut level, mut visible) = (Vec::new(), Vec::new(), Vec::new());
+    for _ in 0.._EPA_MAX_ITERS {
+        let support = SupportPoint::new(&closest.normal, shape1, pose1, shape2, pose2, &mut hints);
+        // the polytope cannot be expanded any further towards this face
+        if support.w.dot(&closest.normal) - closest.d < tol {
+            break;
+        }
+
+        // the faces visible from the new vertex, grown from the closest face across shared edges: a
+        // nearly coplanar face elsewhere that rounding makes look visible is never reached. a neighbour
+        // level with the new vertex (the flat caps of cylinders and cones) is taken along if it would fold.
+        seen.clear();
+        level.clear();
+        for f in 0..faces.len() {
+            if faces[f].sees(&support.w, &polytope) {
+                seen.push(f);
+            } else if faces[f].d.is_finite() && faces[f].normal.dot(&(support.w - polytope[faces[f].vertices[0]].w)) > -tol {
+                level.push(f);
+            }
+        }
+        visible.clear();
+        visible.push(closest_index);
+        let mut next_visible = 0;
+        while next_visible < visible.len() {
+            let f = visible[next_visible];
+            next_visible += 1;
+            for k in 0..3 {
+                let (a, b) = (faces[f].vertices[k], faces[f].vertices[(k + 1) % 3]);
+                let shares_edge = |g: &usize| (0..3).any(|m| faces[*g].vertices[m] == b && faces[*g].vertices[(m + 1) % 3] == a);
+                if visible.iter().any(shares_edge) {
+                    continue;
+                }
+                if let Some(g) = seen.iter().copied().find(shares_edge) {
+                    visible.push(g);
+                } else if let Some(g) = level.iter().copied().find(shares_edge)
+                    .filter(|g| faces[*g].folds_over(&polytope[a].w, &polytope[b].w, &support.w, tol)) {
+                    visible.push(g);
+                }
+            }
+        }
+
+        // remove them and keep track of the horizon
+        let mut horizon: Vec<(usize, usize)> = Vec::new();
+        let mut f = 0;
+        faces.retain(|face| {
+            f += 1;
+            if !visible.contains(&(f - 1)) {
+                return true;
+            }
+            for k in 0..3 {
+                let edge = (face.vertices[k], face.vertices[(k + 1) % 3]);
+                if let Some(pos) = horizon.iter().position(|&(a, b)| a == edge.1 && b == edge.0) {
+                    horizon.swap_remove(pos);
+                } else {
+                    horizon.push(edge);
+                }
+            }
+            false
+        });
+
+        // on a nearly flat polytope rounding can still make the visible faces something other than a
+        // disc, its boundary is then not a single loop and stitching it would break the polytope
+        if horizon.is_empty() || !is_simple_loop(&horizon) {
+            break;
+        }
+
+        // stitch the horizon to the new vertex
+        polytope.push(support);
+        let new_index = polytope.len() - 1;
+        for (a, b) in horizon {
+            faces.push(PolytopeFace::new([a, b, new_index], &polytope, tol));
+        }
+        // the polytope only grows, so its closest face can only move away from the origin. one that
+        // comes closer means rounding (e.g. on the flat caps of cylinders and cones) made it non-convex,
+        // keep the last face found before that
+        let next = closest_face(&faces);
+        if closest.d.is_finite() && faces[next].d < closest.d - tol {
+            break;
+        }
+        closest_index = next;
+        closest = faces[next];
+    }
+
+    if !closest.d.is_finite() {
+        return overlap(&polytope, dir);
+    }
+
+    // project the origin onto the closest face and carry the weights over to both shapes
+    let [ia, ib, ic] = closest.vertices;
+    let (a, b, c) = (&polytope[ia], &polytope[ib], &polytope[ic]);
+    let (u, v, w) = barycentric(&(closest.normal * closest.d), &a.w, &b.w, &c.w, tol);
+    Penetration {
+        depth: closest.d.max(0.0),
+        normal: closest.normal,
+        p1: a.p1 * u + b.p1 * v + c.p1 * w,
+        p2: a.p2 * u + b.p2 * v + c.p2 * w,
+    }
+}
+
+// two overlapping shapes reported without a depth, for when EPA is not run or has no face to measure
+// it on: touching along -dir, and any point of the overlap will do for both witnesses
+pub(crate) fn overlap(points: &[SupportPoint], dir: &V3) -> Penetration {
+    let p = points.iter().fold(V3::zeros(), |acc, s| acc + s.p1 + s.p2) / (2 * points.len()) as f64;
+    let normal = if dir.norm_squared() > 0.0 { -dir.normalize() } else { V3::new(1.0, 0.0, 0.0) };
+    Penetration { depth: 0.0, normal, p1: p, p2: p }
+}
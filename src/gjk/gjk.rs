@@ -1,16 +1,68 @@
 use apollo_rust_spatial::vectors::V3;
-use std::ops::{Add, Div, Neg, Sub};
-use crate::shape::shape::{ConvexPolyhedron, ShapeTrait};
+use std::ops::{Add, Neg, Sub};
+use crate::shape::shape::ShapeTrait;
+use crate::gjk::epa::{epa, overlap, Penetration};
 use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
 use rayon::prelude::*;
+use std::collections::HashMap;
 
 const _PROXIMITY_TOL: f64 =1e-10;
 const _PROXIMITY_MAX_ITERS: usize = 100;
 
+// per-query GJK settings. the tolerances are absolute, so they have to follow the scale of the
+// scene: the defaults suit objects measured in meters.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QueryOptions {
+    pub tolerance: f64, // convergence and origin-containment tolerance
+    pub max_iters: usize, // GJK gives up (GJKResult::NotConverged) after this many iterations
+    pub contact_threshold: f64, // pairs at most this far apart count as colliding
+    pub penetration: bool, // run EPA on overlapping pairs for their depth, otherwise they are reported at distance 0
+}
+
+impl Default for QueryOptions {
+    fn default() -> Self {
+        Self { tolerance: _PROXIMITY_TOL, max_iters: _PROXIMITY_MAX_ITERS, contact_threshold: 0.0, penetration: false }
+    }
+}
+
+impl QueryOptions {
+    // the same options with the contact threshold raised to margin, for a proximity query: compounds
+    // then keep searching and list as touching every part pair the query can report
+    pub fn with_margin(&self, margin: f64) -> QueryOptions {
+        QueryOptions { contact_threshold: self.contact_threshold.max(margin), ..*self }
+    }
+
+    // the same options with EPA run on overlapping pairs
+    pub fn with_penetration(&self) -> QueryOptions {
+        QueryOptions { penetration: true, ..*self }
+    }
+}
+
+// a vertex of the Minkowski difference together with the support points that produced it
+#[derive(Clone, Copy)]
+pub(crate) struct SupportPoint{
+    pub w: V3, // w = p1 - p2
+    pub p1: V3, // support point on shape 1
+    pub p2: V3, // support point on shape 2
+}
+
+impl SupportPoint{
+    pub fn zeros()->Self{
+        Self{w: V3::zeros(), p1: V3::zeros(), p2: V3::zeros()}
+    }
+    // hints are the support_from vertices of both shapes, carried from one query to the next
+    pub fn new<S1: ShapeTrait, S2: ShapeTrait>(dir: &V3, shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2: &LieGroupISE3q, hints: &mut [usize; 2])->Self{
+        let p1 = shape1.support_from(dir, pose1, &mut hints[0]);
+        let p2 = shape2.support_from(&dir.neg(), pose2, &mut hints[1]);
+        Self{w: p1.sub(&p2), p1, p2}
+    }
+}
+
 #[derive(Clone)]
-struct ThreeSimplex{
-    arr: [V3;4],
-    len: usize,
+pub(crate) struct ThreeSimplex{
+    pub arr: [SupportPoint;4],
+    pub len: usize,
+    pub hints: [usize; 2], // where the last support queries on both shapes ended, kept for this query only
 }
 
 #[derive(Clone)]
@@ -22,7 +74,7 @@ struct GJKFeature{
 
 
 impl GJKFeature{
-    pub fn new(v: V3, arr: [V3; 4], len: usize)->Self{
+    pub fn new(v: V3, arr: [SupportPoint; 4], len: usize)->Self{
         Self{v, simplex: ThreeSimplex::new_with_data(arr,len), d:v.norm()}
     }
     pub fn min(self, other: GJKFeature) -> GJKFeature{
@@ -32,176 +84,746 @@ impl GJKFeature{
 
 impl ThreeSimplex {
     pub fn new() -> Self {
-        Self{arr:[V3::zeros();4], len:0}
+        Self{arr:[SupportPoint::zeros();4], len:0, hints: [usize::MAX; 2]}
     }
-    pub fn new_with_data(arr: [V3;4], len: usize) -> Self {
-        Self{arr,len}
+    pub fn new_with_data(arr: [SupportPoint;4], len: usize) -> Self {
+        Self{arr, len, hints: [usize::MAX; 2]}
     }
 
     pub fn len(&self) -> usize {
         self.len
     }
 
-    pub fn add(&mut self, point: V3) {
+    pub fn add(&mut self, point: SupportPoint) {
         self.arr[self.len] = point;
         self.len += 1;
     }
 
-    pub fn find_and_reduce(&mut self) -> (V3, f64) {
-        match self.len {
-            1 => {  (self.arr[0], self.arr[0].norm()) },
-            2 => {let f=closest_to_origin_on_line(&self.arr[0], &self.arr[1]); self.arr=f.simplex.arr;self.len=f.simplex.len; (f.v, f.d)},
-            3 =>{ let f=closest_to_origin_on_triangle(&self.arr[0], &self.arr[1], &self.arr[2]); self.arr=f.simplex.arr;self.len=f.simplex.len; (f.v, f.d)},
-            _ =>{ if tetrahedron_contains_origin(&self.arr[0],&self.arr[1], &self.arr[2], &self.arr[3]) {(V3::zeros(),0.0)} else {let f = closest_to_origin_on_tetrahedron(&self.arr[0],&self.arr[1], &self.arr[2], &self.arr[3]); self.arr=f.simplex.arr;self.len=f.simplex.len; (f.v, f.d)}},
-        }
+    // replaces the simplex by the smallest sub-simplex holding its point closest to the origin,
+    // returns that point and its norm
+    pub fn find_and_reduce(&mut self, tol: f64) -> (V3, f64) {
+        let arr = &self.arr;
+        let f = match self.len {
+            1 => return (arr[0].w, arr[0].w.norm()),
+            2 => closest_on_segment(&arr[0], &arr[1], tol),
+            3 => closest_on_triangle(&arr[0], &arr[1], &arr[2], tol),
+            _ => closest_on_tetrahedron(&arr[0], &arr[1], &arr[2], &arr[3], tol),
+        };
+        self.arr = f.simplex.arr;
+        self.len = f.simplex.len;
+        (f.v, f.d)
     }
+}
 
+// signed-volume distance sub-algorithm: the barycentric coordinates of the origin's projection
+// come from signed lengths, areas and volumes of the sub-simplices, and every sub-simplex opposite
+// a vertex with a non-positive coordinate is searched recursively. a simplex that is degenerate
+// within tol (coincident, collinear or coplanar vertices) is handled through its faces instead, so
+// no division by a vanishing length, area or volume ever happens.
 
+fn closest_on_vertex(a: &SupportPoint) -> GJKFeature {
+    GJKFeature::new(a.w, [*a, SupportPoint::zeros(), SupportPoint::zeros(), SupportPoint::zeros()], 1)
 }
 
-fn closest_to_origin_on_line(a:&V3, b:&V3) -> GJKFeature {
-    let ab = b.sub(a);
-    let t = -(a.dot(&ab)).div(&(ab.dot(&ab)));
-    if t<0.0{
-        GJKFeature::new(a.clone(),[a.clone(),b.clone(),V3::zeros(), V3::zeros()],2)
+fn closest_on_segment(a: &SupportPoint, b: &SupportPoint, tol: f64) -> GJKFeature {
+    let ab = b.w.sub(&a.w);
+    let len2 = ab.dot(&ab);
+    if len2 <= tol * tol {
+        return closest_on_vertex(a).min(closest_on_vertex(b));
     }
-    else if t>1.0{
-        GJKFeature::new(b.clone(),[a.clone(),b.clone(),V3::zeros(), V3::zeros()],2)
-    } else{
-        let closest = ab.scale(t).add(a);
-        GJKFeature::new(closest,[a.clone(),b.clone(),V3::zeros(), V3::zeros()],2)
+    // the origin projects to a + (t / len2) * ab
+    let t = -a.w.dot(&ab);
+    if t <= 0.0 {
+        closest_on_vertex(a)
+    } else if t >= len2 {
+        closest_on_vertex(b)
+    } else {
+        GJKFeature::new(a.w.add(ab.scale(t / len2)), [*a, *b, SupportPoint::zeros(), SupportPoint::zeros()], 2)
     }
 }
 
-fn closest_to_origin_on_triangle(a:&V3, b:&V3,c:&V3) -> GJKFeature {
-    // compute the barycentric coordinates of the projection of the origin.
-    let ab = b.sub(a);
-    let ac = c.sub(a);
-    let ao = a.neg();
-    let d1 = ab.dot(&ao);
-    let d2 = ac.dot(&ao);
-    let d00 = ab.dot(&ab);
-    let d01 = ab.dot(&ac);
-    let d11 = ac.dot(&ac);
-    let denom = d00 * d11 - d01 * d01;
-    let u = (d11 * d1 - d01 * d2) / denom;
-    let v = (d00 * d2 - d01 * d1) / denom;
-
-    // inside
-    if u > 0.0 && v > 0.0 && (u + v) < 1.0 {
-        let closest = u*b+v*c+(1.0-u-v)*a;
-        return GJKFeature::new(closest, [a.clone(), b.clone(), c.clone(), V3::zeros()],3);
+fn closest_on_triangle(a: &SupportPoint, b: &SupportPoint, c: &SupportPoint, tol: f64) -> GJKFeature {
+    let n = b.w.sub(&a.w).cross(&c.w.sub(&a.w));
+    let size2 = b.w.sub(&a.w).norm_squared().max(c.w.sub(&a.w).norm_squared()).max(c.w.sub(&b.w).norm_squared());
+    // twice the area is below tol times the longest edge: collinear
+    if n.norm_squared() <= tol * tol * size2 {
+        return closest_on_segment(a, b, tol).min(closest_on_segment(b, c, tol)).min(closest_on_segment(a, c, tol));
     }
+    // signed areas of the sub-triangles around the projected origin p, measured in the coordinate
+    // plane where the triangle is largest. the area of (a, b, c) in that plane is n[k] itself.
+    let p = n.scale(a.w.dot(&n) / n.dot(&n));
+    let k = n.iamax();
+    let (x, y) = ((k + 1) % 3, (k + 2) % 3);
+    let area = |u: &V3, v: &V3, w: &V3| (v[x] - u[x]) * (w[y] - u[y]) - (v[y] - u[y]) * (w[x] - u[x]);
+    let total = n[k];
+    let la = area(&p, &b.w, &c.w) / total;
+    let lb = area(&a.w, &p, &c.w) / total;
+    let lc = area(&a.w, &b.w, &p) / total;
+    if la > 0.0 && lb > 0.0 && lc > 0.0 {
+        let v = a.w.scale(la).add(b.w.scale(lb)).add(c.w.scale(lc));
+        return GJKFeature::new(v, [*a, *b, *c, SupportPoint::zeros()], 3);
+    }
+    let mut best: Option<GJKFeature> = None;
+    for (l, u, w) in [(la, b, c), (lb, a, c), (lc, a, b)] {
+        if l <= 0.0 {
+            let f = closest_on_segment(u, w, tol);
+            best = Some(match best { Some(g) => g.min(f), None => f });
+        }
+    }
+    best.unwrap()
+}
 
-    // on edge
-    let e1 = closest_to_origin_on_line(a, b);
-    let e2 = closest_to_origin_on_line(b, c);
-    let e3 = closest_to_origin_on_line(a, c);
-    e3.min(e2).min(e1)
+fn closest_on_tetrahedron(a: &SupportPoint, b: &SupportPoint, c: &SupportPoint, d: &SupportPoint, tol: f64) -> GJKFeature {
+    let (wa, wb, wc, wd) = (&a.w, &b.w, &c.w, &d.w);
+    let size2 = (wb - wa).norm_squared().max((wc - wa).norm_squared()).max((wd - wa).norm_squared())
+        .max((wc - wb).norm_squared()).max((wd - wb).norm_squared()).max((wd - wc).norm_squared());
+    // volumes are compared against tol times the squared size of the tetrahedron: it is flat when
+    // its height is within about tol, a length like every other GJK tolerance (see QueryOptions)
+    let eps = tol * size2;
+    let vol = signed_volume(wa, wb, wc, wd);
+    if vol.abs() <= eps {
+        // flat, the closest point lies on one of the faces
+        return closest_on_triangle(a, b, c, tol).min(closest_on_triangle(a, b, d, tol))
+            .min(closest_on_triangle(a, c, d, tol)).min(closest_on_triangle(b, c, d, tol));
+    }
+    // signed volumes of the tetrahedra that replace one vertex with the origin, all of them share
+    // the sign of vol when the origin is inside
+    let o = V3::zeros();
+    let sign = vol.signum();
+    let va = sign * signed_volume(&o, wb, wc, wd);
+    let vb = sign * signed_volume(wa, &o, wc, wd);
+    let vc = sign * signed_volume(wa, wb, &o, wd);
+    let vd = sign * signed_volume(wa, wb, wc, &o);
+    if va > -eps && vb > -eps && vc > -eps && vd > -eps {
+        return GJKFeature::new(o, [*a, *b, *c, *d], 4);
+    }
+    let mut best: Option<GJKFeature> = None;
+    for (l, u, v, w) in [(va, b, c, d), (vb, a, c, d), (vc, a, b, d), (vd, a, b, c)] {
+        if l <= -eps {
+            let f = closest_on_triangle(u, v, w, tol);
+            best = Some(match best { Some(g) => g.min(f), None => f });
+        }
+    }
+    best.unwrap()
 }
 
+fn signed_volume(a: &V3, b: &V3, c: &V3, d: &V3) -> f64 {
+    (b - a).cross(&(c - a)).dot(&(d - a))
+}
 
+// unit vector orthogonal to the affine hull of a segment or triangle simplex, any unit vector for a point
+fn orthogonal_direction(simplex: &ThreeSimplex) -> V3 {
+    let arr = &simplex.arr;
+    match simplex.len() {
+        1 => V3::new(1.0, 0.0, 0.0),
+        2 => {
+            let e = arr[1].w.sub(&arr[0].w);
+            let mut axis = V3::zeros();
+            axis[e.iamin()] = 1.0;
+            e.cross(&axis).normalize()
+        },
+        _ => arr[1].w.sub(&arr[0].w).cross(&arr[2].w.sub(&arr[0].w)).normalize(),
+    }
+}
 
-fn closest_to_origin_on_tetrahedron(a: &V3, b: &V3, c: &V3, d: &V3) -> GJKFeature {
-    let f1 = closest_to_origin_on_triangle(a, b, c);
-    let f2 = closest_to_origin_on_triangle(a, b, d);
-    let f3 = closest_to_origin_on_triangle(a, c, d);
-    let f4 = closest_to_origin_on_triangle(b, c, d);
-    f4.min(f3).min(f2).min(f1)
+// the origin lies on a simplex of fewer than 4 vertices (the shapes touch or the simplex passes
+// through the overlap): add support points off its affine hull until it is a tetrahedron with the
+// origin on its boundary, which EPA can start from. returns the direction in which the Minkowski
+// difference turned out to be flat if that is impossible, the shapes then touch with zero volume.
+fn complete_simplex<S1: ShapeTrait, S2: ShapeTrait>(simplex: &mut ThreeSimplex, shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, tol: f64) -> Option<V3> {
+    while simplex.len() < 4 {
+        let dir = orthogonal_direction(simplex);
+        let base = simplex.arr[0].w;
+        let support = [dir, dir.neg()].into_iter()
+            .map(|d| (d, SupportPoint::new(&d, shape1, pose1, shape2, pose2, &mut simplex.hints)))
+            .find(|(d, support)| support.w.sub(&base).dot(d) > tol);
+        match support {
+            Some((_, support)) => simplex.add(support),
+            None => return Some(dir),
+        }
+    }
+    None
 }
 
-fn signed_volume(a: &V3, b: &V3, c: &V3, d: &V3) -> f64 {
-    (b - a).cross(&(c - a)).dot(&(d - a))
+// outcome of a GJK query. dir is the unit vector along the closest point of the Minkowski difference
+// p1 - p2 (pointing from shape 2 towards shape 1) and distance the separation along it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GJKResult {
+    Separated { dir: V3, distance: f64 },
+    Intersecting,
+    // the iteration limit was hit, distance belongs to the best simplex found and only bounds the true one from above
+    NotConverged { dir: V3, distance: f64 },
 }
 
-fn tetrahedron_contains_origin(a: &V3, b: &V3, c: &V3, d: &V3) -> bool {
-    let vol = signed_volume(a, b, c, d);
-    if vol.abs() < _PROXIMITY_TOL {
-        return false;
+impl GJKResult {
+    // 0 when intersecting
+    pub fn distance(&self) -> f64 {
+        match self {
+            GJKResult::Separated { distance, .. } | GJKResult::NotConverged { distance, .. } => *distance,
+            GJKResult::Intersecting => 0.0,
+        }
     }
 
-    // compute the signed volumes of tetrahedra that replace one vertex with the origin.
-    let v1 = signed_volume(&V3::zeros(), b, c, d);
-    let v2 = signed_volume(a,&V3::zeros(), c, d);
-    let v3 = signed_volume(a, b, &V3::zeros(), d);
-    let v4 = signed_volume(a, b, c, &V3::zeros());
+    // zero when intersecting
+    pub fn dir(&self) -> V3 {
+        match self {
+            GJKResult::Separated { dir, .. } | GJKResult::NotConverged { dir, .. } => *dir,
+            GJKResult::Intersecting => V3::zeros(),
+        }
+    }
 
-    if vol > 0.0 {
-        v1 > -_PROXIMITY_TOL && v2 > -_PROXIMITY_TOL && v3 > -_PROXIMITY_TOL && v4 > -_PROXIMITY_TOL
-    } else {
-        v1 < _PROXIMITY_TOL && v2 < _PROXIMITY_TOL && v3 < _PROXIMITY_TOL && v4 < _PROXIMITY_TOL
+    pub fn converged(&self) -> bool {
+        !matches!(self, GJKResult::NotConverged { .. })
     }
 }
 
+// runs GJK and also hands back the final simplex, which encloses the origin when the shapes intersect
+fn gjk_simplex<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, options: &QueryOptions) -> (ThreeSimplex, GJKResult) {
+    let dir = pose1.0.translation.vector.sub(&pose2.0.translation.vector);
+    gjk_simplex_from(shape1, pose1, shape2, pose2, dir, options)
+}
 
-pub fn gjk_contact<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q) -> (V3, f64) {
+// same as gjk_simplex, the first support point is taken along dir
+fn gjk_simplex_from<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, mut dir: V3, options: &QueryOptions) -> (ThreeSimplex, GJKResult) {
     let mut simplex = ThreeSimplex::new();
-    let mut dir = pose1.0.translation.vector.sub(&pose2.0.translation.vector);
     if dir.norm_squared() > 1e-6 {dir=dir.normalize()} else {dir=V3::new(1.0, 0.0, 0.0)};
-    let mut support = shape1.support(&dir, pose1).sub(shape2.support(&dir.neg(), pose2));
+    let mut support = SupportPoint::new(&dir, shape1, pose1, shape2, pose2, &mut simplex.hints);
     simplex.add(support);
-    let mut dist=support.norm();
+    let mut dist;
     let mut iter=0;
-    while iter < _PROXIMITY_MAX_ITERS {
-        (dir, dist) = simplex.find_and_reduce();
-        // intersected
-        if dist < _PROXIMITY_TOL && simplex.len()==4 {return (V3::zeros(), 0.0);}
+    loop {
+        (dir, dist) = simplex.find_and_reduce(options.tolerance);
+        // the origin is on the simplex: intersected, or touching if the Minkowski difference is flat there
+        if dist < options.tolerance {
+            return match complete_simplex(&mut simplex, shape1, pose1, shape2, pose2, options.tolerance) {
+                None => (simplex, GJKResult::Intersecting),
+                Some(dir) => (simplex, GJKResult::Separated{dir, distance: 0.0}),
+            };
+        }
         dir = dir.normalize();
-        support = shape1.support(&dir.neg(), pose1).sub(shape2.support(&dir, pose2));
-        let proj = support.dot(&dir);
+        // out of iterations, the reduced simplex still matches (dir, dist)
+        if iter == options.max_iters {return (simplex, GJKResult::NotConverged{dir, distance: dist});}
+        support = SupportPoint::new(&dir.neg(), shape1, pose1, shape2, pose2, &mut simplex.hints);
+        let proj = support.w.dot(&dir);
         //the simplex closet to the origin was found
-        if dist < proj+_PROXIMITY_TOL {
-            return (dir, dist);
+        if dist < proj+options.tolerance {
+            return (simplex, GJKResult::Separated{dir, distance: dist});
         }
         // proceed to origin
         simplex.add(support);
         iter+=1;
     }
-    (dir, dist)
 }
 
+// barycentric coordinates of p w.r.t. triangle (a, b, c), p is assumed to lie in its plane.
+// a triangle whose double area is within tol times its longer side at a is degenerate and maps p to a.
+pub(crate) fn barycentric(p: &V3, a: &V3, b: &V3, c: &V3, tol: f64) -> (f64, f64, f64) {
+    let ab = b - a;
+    let ac = c - a;
+    let ap = p - a;
+    let d00 = ab.dot(&ab);
+    let d01 = ab.dot(&ac);
+    let d11 = ac.dot(&ac);
+    let d20 = ap.dot(&ab);
+    let d21 = ap.dot(&ac);
+    let denom = d00 * d11 - d01 * d01;
+    // denom is the squared double area
+    if denom <= tol * tol * d00.max(d11) {
+        return (1.0, 0.0, 0.0);
+    }
+    let v = (d11 * d20 - d01 * d21) / denom;
+    let w = (d00 * d21 - d01 * d20) / denom;
+    (1.0 - v - w, v, w)
+}
+
+// closest points on both shapes, recovered from where v = p1 - p2 sits on the reduced simplex
+fn witness_points(simplex: &ThreeSimplex, v: &V3, tol: f64) -> (V3, V3) {
+    let arr = &simplex.arr;
+    match simplex.len() {
+        1 => (arr[0].p1, arr[0].p2),
+        2 => {
+            let ab = arr[1].w.sub(&arr[0].w);
+            let len2 = ab.dot(&ab);
+            let t = if len2 <= tol * tol { 0.0 } else { (v.sub(&arr[0].w).dot(&ab) / len2).clamp(0.0, 1.0) };
+            (arr[0].p1.scale(1.0 - t).add(arr[1].p1.scale(t)), arr[0].p2.scale(1.0 - t).add(arr[1].p2.scale(t)))
+        },
+        _ => {
+            let (u, s, t) = barycentric(v, &arr[0].w, &arr[1].w, &arr[2].w, tol);
+            (arr[0].p1 * u + arr[1].p1 * s + arr[2].p1 * t, arr[0].p2 * u + arr[1].p2 * s + arr[2].p2 * t)
+        },
+    }
+}
+
+pub fn gjk_contact<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q) -> GJKResult {
+    gjk_contact_with(shape1, pose1, shape2, pose2, &QueryOptions::default())
+}
+
+// the support of a compound only describes the hull of its parts, so compounds go through the
+// contact of their parts instead: they intersect as soon as one part pair does. that needs the
+// depth to tell overlapping parts from touching ones.
+pub fn gjk_contact_with<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, options: &QueryOptions) -> GJKResult {
+    if shape1.as_compound().is_some() || shape2.as_compound().is_some() {
+        let c = Contact::with_options(0, 1, shape1, pose1, shape2, pose2, &options.with_penetration());
+        return if c.distance < 0.0 {
+            GJKResult::Intersecting
+        } else if c.converged {
+            GJKResult::Separated { dir: c.normal.neg(), distance: c.distance }
+        } else {
+            GJKResult::NotConverged { dir: c.normal.neg(), distance: c.distance }
+        };
+    }
+    gjk_simplex(shape1, pose1, shape2, pose2, options).1
+}
+
+// GJK followed by EPA on the enclosing simplex; None if the shapes do not overlap
+pub fn gjk_penetration<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q) -> Option<Penetration> {
+    gjk_penetration_with(shape1, pose1, shape2, pose2, &QueryOptions::default())
+}
+
+// compounds report the deepest part pair
+pub fn gjk_penetration_with<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, options: &QueryOptions) -> Option<Penetration> {
+    if shape1.as_compound().is_some() || shape2.as_compound().is_some() {
+        let c = Contact::with_options(0, 1, shape1, pose1, shape2, pose2, &options.with_penetration());
+        return (c.distance < 0.0).then_some(Penetration { depth: -c.distance, normal: c.normal, p1: c.p1, p2: c.p2 });
+    }
+    let dir = pose1.0.translation.vector.sub(&pose2.0.translation.vector);
+    let (simplex, result) = gjk_simplex_from(shape1, pose1, shape2, pose2, dir, options);
+    (result == GJKResult::Intersecting).then(|| epa(&simplex, shape1, pose1, shape2, pose2, &dir, options.tolerance))
+}
+
+// boolean intersection test, skips EPA for convex pairs and goes through the parts of compounds.
+// a pair GJK could not settle counts as intersecting, the same way the narrow phases report it.
+pub fn shapes_intersect<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q) -> bool {
+    shapes_intersect_with(shape1, pose1, shape2, pose2, &QueryOptions::default())
+}
+
+pub fn shapes_intersect_with<S1: ShapeTrait, S2: ShapeTrait>(shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, options: &QueryOptions) -> bool {
+    if shape1.as_compound().is_some() || shape2.as_compound().is_some() {
+        return Contact::with_options(0, 1, shape1, pose1, shape2, pose2, options).is_within(options.contact_threshold);
+    }
+    let result = gjk_contact_with(shape1, pose1, shape2, pose2, options);
+    !result.converged() || result.distance() <= options.contact_threshold
+}
+
+// all vectors are in world frame, and p2 - p1 = distance * normal holds in both cases
 #[derive(Debug)]
 pub struct Contact {
     pub i: usize,
     pub j: usize,
+    pub p1: V3, // closest point on shape i, or its deepest point inside shape j (with options.penetration, else a point of the overlap)
+    pub p2: V3, // closest point on shape j, or its deepest point inside shape i (with options.penetration, else the same point as p1)
+    pub distance: f64, // separating distance, negative penetration depth when overlapping (0 without options.penetration)
+    pub normal: V3, // unit vector pointing from shape i towards shape j
+    pub parts: Option<Vec<(usize, usize)>>, // touching (part of i, part of j) if either shape is a compound, None for two convex shapes
+    pub converged: bool, // false if GJK gave up, distance is then only an upper bound
+}
+
+impl Contact {
+    pub fn new<S1: ShapeTrait, S2: ShapeTrait>(i: usize, j: usize, shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q) -> Self {
+        Self::with_options(i, j, shape1, pose1, shape2, pose2, &QueryOptions::default())
+    }
+
+    // parts of compounds within options.contact_threshold of each other are listed as touching
+    pub fn with_options<S1: ShapeTrait, S2: ShapeTrait>(i: usize, j: usize, shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, options: &QueryOptions) -> Self {
+        let dir = pose1.0.translation.vector.sub(&pose2.0.translation.vector);
+        Self::warm_started_with(i, j, shape1, pose1, shape2, pose2, &dir, options)
+    }
+
+    // GJK starts from the support point along dir instead of along the line between the two
+    // origins. the normal this pair had in the previous frame is a good guess for nearby poses.
+    // compounds query each part from scratch and ignore it.
+    pub fn warm_started<S1: ShapeTrait, S2: ShapeTrait>(i: usize, j: usize, shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, dir: &V3) -> Self {
+        Self::warm_started_with(i, j, shape1, pose1, shape2, pose2, dir, &QueryOptions::default())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn warm_started_with<S1: ShapeTrait, S2: ShapeTrait>(i: usize, j: usize, shape1: &S1, pose1: &LieGroupISE3q, shape2: &S2, pose2:&LieGroupISE3q, dir: &V3, options: &QueryOptions) -> Self {
+        if let Some(compound) = shape1.as_compound() {
+            return compound.contact_with(i, j, pose1, shape2, pose2, options);
+        }
+        if let Some(compound) = shape2.as_compound() {
+            return compound.contact_with(j, i, pose2, shape1, pose1, options).flipped();
+        }
+        let (simplex, result) = gjk_simplex_from(shape1, pose1, shape2, pose2, *dir, options);
+        if result == GJKResult::Intersecting {
+            let pen = if options.penetration {
+                epa(&simplex, shape1, pose1, shape2, pose2, dir, options.tolerance)
+            } else {
+                overlap(&simplex.arr[..simplex.len()], dir)
+            };
+            return Self{i, j, p1: pen.p1, p2: pen.p2, distance: -pen.depth, normal: pen.normal, parts: None, converged: true};
+        }
+        let (dir, dist) = (result.dir(), result.distance());
+        let (p1, p2) = witness_points(&simplex, &dir.scale(dist), options.tolerance);
+        Self{i, j, p1, p2, distance: dist, normal: dir.neg(), parts: None, converged: result.converged()}
+    }
+
+    // whether a narrow phase with this margin reports the contact: close enough, or not known to be apart
+    pub fn is_within(&self, margin: f64) -> bool {
+        self.distance <= margin || !self.converged
+    }
+
+    // the same contact seen from the other shape
+    pub fn flipped(self) -> Self {
+        Self{i: self.j, j: self.i, p1: self.p2, p2: self.p1, distance: self.distance, normal: self.normal.neg(),
+            parts: self.parts.map(|parts| parts.into_iter().map(|(a, b)| (b, a)).collect()), converged: self.converged}
+    }
+}
+
+pub fn serial_narrow_phase_check<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+)-> Vec<Contact>{
+    serial_narrow_phase_check_with(pairs, shapes, poses, &QueryOptions::default())
 }
 
-pub fn serial_narrow_phase_check(
+// keeps the pairs within options.contact_threshold of each other
+pub fn serial_narrow_phase_check_with<S: ShapeTrait + Sync>(
     pairs:   &[(usize, usize)],
-    shapes:  &[ConvexPolyhedron],
+    shapes:  &[S],
     poses:   &[LieGroupISE3q],
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    serial_narrow_phase_proximity_with(pairs, shapes, poses, options.contact_threshold, options)
+}
+
+// embarrassingly parallelized narrow phase using Rayon parallel iterator
+pub fn parallel_narrow_phase_check<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+) -> Vec<Contact>
+{
+    parallel_narrow_phase_check_with(pairs, shapes, poses, &QueryOptions::default())
+}
+
+pub fn parallel_narrow_phase_check_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    options: &QueryOptions,
+) -> Vec<Contact>
+{
+    parallel_narrow_phase_proximity_with(pairs, shapes, poses, options.contact_threshold, options)
+}
+
+// keeps the pairs whose signed distance is within margin, margin = 0 keeps exactly the colliding ones
+pub fn serial_narrow_phase_proximity<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    margin:  f64,
+)-> Vec<Contact>{
+    serial_narrow_phase_proximity_with(pairs, shapes, poses, margin, &QueryOptions::default())
+}
+
+// margin takes the place of options.contact_threshold, compounds list every part pair within the larger of the two
+pub fn serial_narrow_phase_proximity_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    margin:  f64,
+    options: &QueryOptions,
 )-> Vec<Contact>{
     assert_eq!(shapes.len(), poses.len(),
                "shapes and poses slices must have the same length");
+    let options = &options.with_margin(margin);
     pairs.iter().filter_map(
         |&(i, j)
         | {
-            let (p, d) = gjk_contact(
+            let c = Contact::with_options(
+                i, j,
                 &shapes[i], &poses[i],
                 &shapes[j], &poses[j],
+                options,
             );
-            (d == 0.0).then(|| Contact { i, j})
+            c.is_within(margin).then_some(c)
         }).collect()
 }
 
-// embarrassingly parallelized narrow phase using Rayon parallel iterator
-pub fn parallel_narrow_phase_check(
+pub fn parallel_narrow_phase_proximity<S: ShapeTrait + Sync>(
     pairs:   &[(usize, usize)],
-    shapes:  &[ConvexPolyhedron],
+    shapes:  &[S],
     poses:   &[LieGroupISE3q],
+    margin:  f64,
+) -> Vec<Contact>
+{
+    parallel_narrow_phase_proximity_with(pairs, shapes, poses, margin, &QueryOptions::default())
+}
+
+pub fn parallel_narrow_phase_proximity_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    margin:  f64,
+    options: &QueryOptions,
 ) -> Vec<Contact>
 {
     assert_eq!(shapes.len(), poses.len(),
                "shapes and poses slices must have the same length");
-
+    let options = &options.with_margin(margin);
     pairs.par_iter()
         .filter_map(
             |&(i, j)
             | {
-            let (p, d) = gjk_contact(
+            let c = Contact::with_options(
+                i, j,
                 &shapes[i], &poses[i],
                 &shapes[j], &poses[j],
+                options,
             );
-            (d ==0.0).then(|| Contact { i, j})
+            c.is_within(margin).then_some(c)
+        }).collect()
+}
+
+// same as serial_narrow_phase_check, pairs rejected by accept are dropped before any GJK work
+pub fn serial_narrow_phase_check_filtered<S: ShapeTrait + Sync, F: Fn(usize, usize) -> bool>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    accept:  &F,
+)-> Vec<Contact>{
+    serial_narrow_phase_check_filtered_with(pairs, shapes, poses, accept, &QueryOptions::default())
+}
+
+pub fn serial_narrow_phase_check_filtered_with<S: ShapeTrait + Sync, F: Fn(usize, usize) -> bool>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    accept:  &F,
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    assert_eq!(shapes.len(), poses.len(),
+               "shapes and poses slices must have the same length");
+    pairs.iter().filter(|&&(i, j)| accept(i, j)).filter_map(
+        |&(i, j)
+        | {
+            let c = Contact::with_options(i, j, &shapes[i], &poses[i], &shapes[j], &poses[j], options);
+            c.is_within(options.contact_threshold).then_some(c)
+        }).collect()
+}
+
+pub fn parallel_narrow_phase_check_filtered<S: ShapeTrait + Sync, F: Fn(usize, usize) -> bool + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    accept:  &F,
+)-> Vec<Contact>{
+    parallel_narrow_phase_check_filtered_with(pairs, shapes, poses, accept, &QueryOptions::default())
+}
+
+pub fn parallel_narrow_phase_check_filtered_with<S: ShapeTrait + Sync, F: Fn(usize, usize) -> bool + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    accept:  &F,
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    assert_eq!(shapes.len(), poses.len(),
+               "shapes and poses slices must have the same length");
+    pairs.par_iter().filter(|&&(i, j)| accept(i, j)).filter_map(
+        |&(i, j)
+        | {
+            let c = Contact::with_options(i, j, &shapes[i], &poses[i], &shapes[j], &poses[j], options);
+            c.is_within(options.contact_threshold).then_some(c)
+        }).collect()
+}
+
+// same as serial_narrow_phase_check, but keeps every processed pair, separated or not
+pub fn serial_narrow_phase_distance<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+)-> Vec<Contact>{
+    serial_narrow_phase_distance_with(pairs, shapes, poses, &QueryOptions::default())
+}
+
+// options.contact_threshold is unused, every pair is kept
+pub fn serial_narrow_phase_distance_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    assert_eq!(shapes.len(), poses.len(),
+               "shapes and poses slices must have the same length");
+    pairs.iter().map(
+        |&(i, j)
+        | Contact::with_options(i, j, &shapes[i], &poses[i], &shapes[j], &poses[j], options)
+    ).collect()
+}
+
+pub fn parallel_narrow_phase_distance<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+)-> Vec<Contact>{
+    parallel_narrow_phase_distance_with(pairs, shapes, poses, &QueryOptions::default())
+}
+
+pub fn parallel_narrow_phase_distance_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    assert_eq!(shapes.len(), poses.len(),
+               "shapes and poses slices must have the same length");
+    pairs.par_iter().map(
+        |&(i, j)
+        | Contact::with_options(i, j, &shapes[i], &poses[i], &shapes[j], &poses[j], options)
+    ).collect()
+}
+
+// narrow phase between two shape sets, i indexes shapes1 and j indexes shapes2
+pub fn serial_bipartite_narrow_phase_check<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes1: &[S1],
+    poses1:  &[LieGroupISE3q],
+    shapes2: &[S2],
+    poses2:  &[LieGroupISE3q],
+)-> Vec<Contact>{
+    serial_bipartite_narrow_phase_check_with(pairs, shapes1, poses1, shapes2, poses2, &QueryOptions::default())
+}
+
+pub fn serial_bipartite_narrow_phase_check_with<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes1: &[S1],
+    poses1:  &[LieGroupISE3q],
+    shapes2: &[S2],
+    poses2:  &[LieGroupISE3q],
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    assert_eq!(shapes1.len(), poses1.len(),
+               "shapes and poses slices must have the same length");
+    assert_eq!(shapes2.len(), poses2.len(),
+               "shapes and poses slices must have the same length");
+    pairs.iter().filter_map(
+        |&(i, j)
+        | {
+            let c = Contact::with_options(i, j, &shapes1[i], &poses1[i], &shapes2[j], &poses2[j], options);
+            c.is_within(options.contact_threshold).then_some(c)
+        }).collect()
+}
+
+pub fn parallel_bipartite_narrow_phase_check<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes1: &[S1],
+    poses1:  &[LieGroupISE3q],
+    shapes2: &[S2],
+    poses2:  &[LieGroupISE3q],
+)-> Vec<Contact>{
+    parallel_bipartite_narrow_phase_check_with(pairs, shapes1, poses1, shapes2, poses2, &QueryOptions::default())
+}
+
+pub fn parallel_bipartite_narrow_phase_check_with<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes1: &[S1],
+    poses1:  &[LieGroupISE3q],
+    shapes2: &[S2],
+    poses2:  &[LieGroupISE3q],
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    assert_eq!(shapes1.len(), poses1.len(),
+               "shapes and poses slices must have the same length");
+    assert_eq!(shapes2.len(), poses2.len(),
+               "shapes and poses slices must have the same length");
+    pairs.par_iter().filter_map(
+        |&(i, j)
+        | {
+            let c = Contact::with_options(i, j, &shapes1[i], &poses1[i], &shapes2[j], &poses2[j], options);
+            c.is_within(options.contact_threshold).then_some(c)
         }).collect()
-}
\ No newline at end of file
+}
+
+// warm-start directions for GJK across frames, keyed by the pair (i, j) as it appears in the
+// pair list. a cached narrow phase seeds every pair with its normal from the previous call and
+// then keeps only the pairs it just processed, so pairs that left the broad phase are dropped.
+#[derive(Debug, Default, Clone)]
+pub struct GJKCache {
+    directions: HashMap<(usize, usize), V3>,
+}
+
+impl GJKCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.directions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.directions.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.directions.clear();
+    }
+
+    pub fn direction(&self, i: usize, j: usize) -> Option<&V3> {
+        self.directions.get(&(i, j))
+    }
+
+    fn contact<S: ShapeTrait>(&self, i: usize, j: usize, shapes: &[S], poses: &[LieGroupISE3q], options: &QueryOptions) -> Contact {
+        match self.direction(i, j) {
+            Some(dir) => Contact::warm_started_with(i, j, &shapes[i], &poses[i], &shapes[j], &poses[j], dir, options),
+            None => Contact::with_options(i, j, &shapes[i], &poses[i], &shapes[j], &poses[j], options),
+        }
+    }
+}
+
+// same as serial_narrow_phase_check, GJK on every pair is seeded from cache and cache is refreshed
+pub fn serial_narrow_phase_check_cached<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    cache:   &mut GJKCache,
+)-> Vec<Contact>{
+    serial_narrow_phase_check_cached_with(pairs, shapes, poses, cache, &QueryOptions::default())
+}
+
+pub fn serial_narrow_phase_check_cached_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    cache:   &mut GJKCache,
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    assert_eq!(shapes.len(), poses.len(),
+               "shapes and poses slices must have the same length");
+    let contacts: Vec<Contact> = pairs.iter().map(
+        |&(i, j)
+        | cache.contact(i, j, shapes, poses, options)
+    ).collect();
+    cache.directions = contacts.iter().map(|c| ((c.i, c.j), c.normal)).collect();
+    contacts.into_iter().filter(|c| c.is_within(options.contact_threshold)).collect()
+}
+
+pub fn parallel_narrow_phase_check_cached<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    cache:   &mut GJKCache,
+)-> Vec<Contact>{
+    parallel_narrow_phase_check_cached_with(pairs, shapes, poses, cache, &QueryOptions::default())
+}
+
+pub fn parallel_narrow_phase_check_cached_with<S: ShapeTrait + Sync>(
+    pairs:   &[(usize, usize)],
+    shapes:  &[S],
+    poses:   &[LieGroupISE3q],
+    cache:   &mut GJKCache,
+    options: &QueryOptions,
+)-> Vec<Contact>{
+    assert_eq!(shapes.len(), poses.len(),
+               "shapes and poses slices must have the same length");
+    let seeds = &*cache;
+    let contacts: Vec<Contact> = pairs.par_iter().map(
+        |&(i, j)
+        | seeds.contact(i, j, shapes, poses, options)
+    ).collect();
+    cache.directions = contacts.par_iter().map(|c| ((c.i, c.j), c.normal)).collect();
+    contacts.into_par_iter().filter(|c| c.is_within(options.contact_threshold)).collect()
+}
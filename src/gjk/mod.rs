@@ -1 +1,4 @@
-pub mod gjk;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod gjk;
+pub mod epa;
+pub mod ccd;
\ No newline at end of file
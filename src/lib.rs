@@ -1,18 +1,18 @@
 #![feature(iter_partition_in_place)]
 
-use std::collections::HashSet;
-use std::hash::Hash;
-use std::sync::Mutex;
-use std::time::Instant;
 use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
 use apollo_rust_spatial::vectors::V3;
-use crate::bvh::par_bvh::{parallel_broad_phase_check, parallel_build_bvh};
-use crate::bvh::srl_bvh::{serial_broad_phase_check, serial_build_bvh};
-use crate::bvh::structs::AABB;
+use crate::bvh::par_bvh::{parallel_bipartite_broad_phase_check, parallel_build_bvh, parallel_self_broad_phase_any, parallel_self_broad_phase_check, parallel_self_broad_phase_check_filtered};
+use crate::bvh::srl_bvh::{serial_bipartite_broad_phase_check, serial_build_bvh, serial_self_broad_phase_any, serial_self_broad_phase_check, serial_self_broad_phase_check_filtered};
+use crate::bvh::dynamic::DynamicBVH;
+use crate::bvh::flat::{parallel_build_flat_bvh, parallel_flat_self_broad_phase_check, serial_build_flat_bvh, serial_flat_self_broad_phase_check};
+use crate::bvh::structs::{AABB, BVHNode};
 use crate::gjk::gjk::*;
+use crate::gjk::ccd::{parallel_narrow_phase_time_of_impact_with, serial_narrow_phase_time_of_impact_with, swept_aabb, TimeOfImpact};
+use crate::filter::PairFilter;
 use crate::shape::shape::ShapeTrait;
-use parry3d_f64::shape::{ConvexPolyhedron as ParryConvexHull, TriMesh};
-use parry3d_f64::query::{distance as parry_distance, DefaultQueryDispatcher};
+use parry3d_f64::shape::ConvexPolyhedron as ParryConvexHull;
+use parry3d_f64::query::{contact as parry_contact, distance as parry_distance};
 use crate::shape::shape::ConvexPolyhedron as ConvexHull;
 use parry3d_f64::math::Point as ParryPoint;
 use rand::Rng;
@@ -23,6 +23,9 @@ use rayon::prelude::*;
 pub mod shape;
 pub mod gjk;
 pub mod bvh;
+pub mod world;
+pub mod filter;
+pub mod acm;
 
 pub fn generate_random_hulls(n: usize, vn_range: (usize, usize), point_range: (V3, V3)) -> Vec<ConvexHull> {
     let mut rng = rand::thread_rng();
@@ -44,22 +47,111 @@ pub fn generate_random_hulls(n: usize, vn_range: (usize, usize), point_range: (V
 pub fn my_hulls_to_parry_hulls(hulls: &[ConvexHull])->Vec<ParryConvexHull>{
     let mut parry_hulls: Vec<ParryConvexHull> = Vec::new();
     for h in hulls.iter(){
-        let pts = h.0.points.iter().map(|p| ParryPoint::from_slice(p)).collect();
-        let faces: Vec<_>  = h.0.indices.iter().map(|f| [f[0] as u32, f[1] as u32, f[2] as u32]).collect();
+        let pts = h.mesh().points.iter().map(|p| ParryPoint::from_slice(p)).collect();
+        let faces: Vec<_>  = h.mesh().indices.iter().map(|f| [f[0] as u32, f[1] as u32, f[2] as u32]).collect();
         parry_hulls.push(ParryConvexHull::from_convex_mesh(pts, &faces).unwrap());
     }
     parry_hulls
 }
 
-pub fn parallel_double_phase_collision_check(shapes: &[ConvexHull],
-                                             poses: &[LieGroupISE3q],
-                                             cut_off: usize)->Vec<Contact>{
-    // construct aabbs
-    //let t=Instant::now();
-    let aabbs:Vec<AABB>  = shapes.par_iter()
+// box of every shape at its pose
+pub fn parallel_build_aabbs<S: ShapeTrait + Sync>(shapes: &[S], poses: &[LieGroupISE3q])->Vec<AABB>{
+    parallel_build_aabbs_with(shapes, poses, 0.0)
+}
+
+pub fn serial_build_aabbs<S: ShapeTrait + Sync>(shapes: &[S], poses: &[LieGroupISE3q])->Vec<AABB>{
+    serial_build_aabbs_with(shapes, poses, 0.0)
+}
+
+// every box grown by margin on each side
+pub fn parallel_build_aabbs_with<S: ShapeTrait + Sync>(shapes: &[S], poses: &[LieGroupISE3q], margin: f64)->Vec<AABB>{
+    shapes.par_iter()
         .zip(poses.par_iter()).
         map(|(shape, pose)|{ let (min,max)=shape.aabb(pose);
-    AABB::new(min,max)}).collect();
+            AABB::new(min,max).inflated(margin)}).collect()
+}
+
+pub fn serial_build_aabbs_with<S: ShapeTrait + Sync>(shapes: &[S], poses: &[LieGroupISE3q], margin: f64)->Vec<AABB>{
+    shapes.iter()
+        .zip(poses.iter()).
+        map(|(shape, pose)|{ let (min,max)=shape.aabb(pose);
+            AABB::new(min,max).inflated(margin)}).collect()
+}
+
+// box of everything every shape covers while moving from its start to its end pose
+pub fn parallel_build_swept_aabbs<S: ShapeTrait + Sync>(shapes: &[S], start_poses: &[LieGroupISE3q], end_poses: &[LieGroupISE3q])->Vec<AABB>{
+    parallel_build_swept_aabbs_with(shapes, start_poses, end_poses, 0.0)
+}
+
+pub fn serial_build_swept_aabbs<S: ShapeTrait + Sync>(shapes: &[S], start_poses: &[LieGroupISE3q], end_poses: &[LieGroupISE3q])->Vec<AABB>{
+    serial_build_swept_aabbs_with(shapes, start_poses, end_poses, 0.0)
+}
+
+pub fn parallel_build_swept_aabbs_with<S: ShapeTrait + Sync>(shapes: &[S], start_poses: &[LieGroupISE3q], end_poses: &[LieGroupISE3q], margin: f64)->Vec<AABB>{
+    shapes.par_iter()
+        .zip(start_poses.par_iter().zip(end_poses.par_iter())).
+        map(|(shape, (start, end))|{ let (min,max)=swept_aabb(shape, start, end);
+            AABB::new(min,max).inflated(margin)}).collect()
+}
+
+pub fn serial_build_swept_aabbs_with<S: ShapeTrait + Sync>(shapes: &[S], start_poses: &[LieGroupISE3q], end_poses: &[LieGroupISE3q], margin: f64)->Vec<AABB>{
+    shapes.iter()
+        .zip(start_poses.iter().zip(end_poses.iter())).
+        map(|(shape, (start, end))|{ let (min,max)=swept_aabb(shape, start, end);
+            AABB::new(min,max).inflated(margin)}).collect()
+}
+
+pub fn parallel_double_phase_collision_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                   poses: &[LieGroupISE3q],
+                                                                   cut_off: usize)->Vec<Contact>{
+    parallel_double_phase_collision_check_with(shapes, poses, cut_off, &QueryOptions::default())
+}
+
+pub fn serial_double_phase_collision_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                 poses: &[LieGroupISE3q],
+                                                                 cut_off: usize)->Vec<Contact>{
+    serial_double_phase_collision_check_with(shapes, poses, cut_off, &QueryOptions::default())
+}
+
+// GJK runs with the given tolerances, pairs within options.contact_threshold count as colliding
+pub fn parallel_double_phase_collision_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                        poses: &[LieGroupISE3q],
+                                                                        cut_off: usize,
+                                                                        options: &QueryOptions)->Vec<Contact>{
+    parallel_double_phase_proximity_check_with(shapes, poses, cut_off, options.contact_threshold, options)
+}
+
+pub fn serial_double_phase_collision_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                      poses: &[LieGroupISE3q],
+                                                                      cut_off: usize,
+                                                                      options: &QueryOptions)->Vec<Contact>{
+    serial_double_phase_proximity_check_with(shapes, poses, cut_off, options.contact_threshold, options)
+}
+
+// reports every pair closer than margin (penetrating pairs included) together with its distance
+pub fn parallel_double_phase_proximity_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                   poses: &[LieGroupISE3q],
+                                                                   cut_off: usize,
+                                                                   margin: f64)->Vec<Contact>{
+    parallel_double_phase_proximity_check_with(shapes, poses, cut_off, margin, &QueryOptions::default())
+}
+
+pub fn serial_double_phase_proximity_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                 poses: &[LieGroupISE3q],
+                                                                 cut_off: usize,
+                                                                 margin: f64)->Vec<Contact>{
+    serial_double_phase_proximity_check_with(shapes, poses, cut_off, margin, &QueryOptions::default())
+}
+
+// margin takes the place of options.contact_threshold
+pub fn parallel_double_phase_proximity_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                        poses: &[LieGroupISE3q],
+                                                                        cut_off: usize,
+                                                                        margin: f64,
+                                                                        options: &QueryOptions)->Vec<Contact>{
+    // construct aabbs, each grown by half the margin so boxes closer than margin overlap
+    //let t=Instant::now();
+    let aabbs = parallel_build_aabbs_with(shapes, poses, 0.5*margin);
     //println!("para build AABBs {:?}",t.elapsed());
     
     // broad phase
@@ -68,28 +160,25 @@ pub fn parallel_double_phase_collision_check(shapes: &[ConvexHull],
     let bvh = parallel_build_bvh(&mut indices, &aabbs, cut_off);
    // println!("para build BVH {:?}", t.elapsed());
 
-    //let potential_pairs: Mutex<Vec<(usize, usize)>> = Mutex::new(Vec::new());
     //let t = Instant::now();
-    let pairs=parallel_broad_phase_check(&*bvh, &*bvh);
+    let pairs=parallel_self_broad_phase_check(&*bvh);
     //println!("para broad check {:?}", t.elapsed());
     // narrow phase
-    //let pairs:Vec<_>=potential_pairs.into_inner().unwrap();
     //let t=Instant::now();
-   let ret=parallel_narrow_phase_check(&pairs, shapes, poses);
+   let ret=parallel_narrow_phase_proximity_with(&pairs, shapes, poses, margin, options);
    // println!("para narrow check {:?}", t.elapsed());
     ret
 
 }
 
-pub fn serial_double_phase_collision_check(shapes: &[ConvexHull],
-                                           poses: &[LieGroupISE3q],
-                                           cut_off: usize)->Vec<Contact>{
-    // construct aabbs
+pub fn serial_double_phase_proximity_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                      poses: &[LieGroupISE3q],
+                                                                      cut_off: usize,
+                                                                      margin: f64,
+                                                                      options: &QueryOptions)->Vec<Contact>{
+    // construct aabbs, each grown by half the margin so boxes closer than margin overlap
     //let t= Instant::now();
-    let aabbs:Vec<AABB>  = shapes.iter()
-        .zip(poses.iter()).
-        map(|(shape, pose)|{ let (min,max)=shape.aabb(pose);
-            AABB::new(min,max)}).collect();
+    let aabbs = serial_build_aabbs_with(shapes, poses, 0.5*margin);
    // println!("serial build AABBs {:?}",t.elapsed());
     
     // broad phase
@@ -100,23 +189,328 @@ pub fn serial_double_phase_collision_check(shapes: &[ConvexHull],
 
     
     //let t = Instant::now();
-    let pairs = serial_broad_phase_check(&*bvh, &*bvh);
+    let pairs = serial_self_broad_phase_check(&*bvh);
     //println!("serial broad check {:?}", t.elapsed());
     // narrow phase
-   // let pairs:Vec<_> = potential_pairs.into_iter().collect();
     //let t = Instant::now();
-   let ret= serial_narrow_phase_check(&pairs, shapes, poses);
+   let ret= serial_narrow_phase_proximity_with(&pairs, shapes, poses, margin, options);
     //println!("serial narrow check {:?}", t.elapsed());
     ret
 
 }
 
+// same pipeline on the array-based FlatBVH instead of the Box<dyn BVHNode> tree
+pub fn parallel_flat_double_phase_collision_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                        poses: &[LieGroupISE3q],
+                                                                        cut_off: usize)->Vec<Contact>{
+    parallel_flat_double_phase_collision_check_with(shapes, poses, cut_off, &QueryOptions::default())
+}
+
+pub fn serial_flat_double_phase_collision_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                      poses: &[LieGroupISE3q],
+                                                                      cut_off: usize)->Vec<Contact>{
+    serial_flat_double_phase_collision_check_with(shapes, poses, cut_off, &QueryOptions::default())
+}
+
+pub fn parallel_flat_double_phase_collision_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                             poses: &[LieGroupISE3q],
+                                                                             cut_off: usize,
+                                                                             options: &QueryOptions)->Vec<Contact>{
+    let aabbs = parallel_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = parallel_build_flat_bvh(&mut indices, &aabbs, cut_off);
+    let pairs = parallel_flat_self_broad_phase_check(&bvh);
+    parallel_narrow_phase_check_with(&pairs, shapes, poses, options)
+}
+
+pub fn serial_flat_double_phase_collision_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                           poses: &[LieGroupISE3q],
+                                                                           cut_off: usize,
+                                                                           options: &QueryOptions)->Vec<Contact>{
+    let aabbs = serial_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = serial_build_flat_bvh(&mut indices, &aabbs, cut_off);
+    let pairs = serial_flat_self_broad_phase_check(&bvh);
+    serial_narrow_phase_check_with(&pairs, shapes, poses, options)
+}
+
+// same as parallel_double_phase_collision_check, but keeps the BVH alive between calls and only
+// refits it to the new poses. build the BVH with new_incremental_bvh the first time.
+pub fn parallel_incremental_collision_check<S: ShapeTrait + Sync>(bvh: &mut DynamicBVH,
+                                                                  shapes: &[S],
+                                                                  poses: &[LieGroupISE3q])->Vec<Contact>{
+    parallel_incremental_collision_check_with(bvh, shapes, poses, &QueryOptions::default())
+}
+
+pub fn parallel_incremental_collision_check_with<S: ShapeTrait + Sync>(bvh: &mut DynamicBVH,
+                                                                       shapes: &[S],
+                                                                       poses: &[LieGroupISE3q],
+                                                                       options: &QueryOptions)->Vec<Contact>{
+    let aabbs = parallel_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    bvh.set_aabbs(aabbs);
+    bvh.refit();
+    let pairs=parallel_self_broad_phase_check(bvh.root());
+    parallel_narrow_phase_check_with(&pairs, shapes, poses, options)
+}
+
+pub fn new_incremental_bvh<S: ShapeTrait + Sync>(shapes: &[S],
+                                                 poses: &[LieGroupISE3q],
+                                                 cut_off: usize)->DynamicBVH{
+    let aabbs = parallel_build_aabbs(shapes, poses);
+    DynamicBVH::new(aabbs, cut_off)
+}
+
+// boolean validity query: GJK runs inside the BVH traversal and everything stops at the first contact
+pub fn parallel_any_collision<S: ShapeTrait + Sync>(shapes: &[S],
+                                                    poses: &[LieGroupISE3q],
+                                                    cut_off: usize)->bool{
+    parallel_any_collision_with(shapes, poses, cut_off, &QueryOptions::default())
+}
+
+pub fn serial_any_collision<S: ShapeTrait + Sync>(shapes: &[S],
+                                                  poses: &[LieGroupISE3q],
+                                                  cut_off: usize)->bool{
+    serial_any_collision_with(shapes, poses, cut_off, &QueryOptions::default())
+}
+
+pub fn parallel_any_collision_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                         poses: &[LieGroupISE3q],
+                                                         cut_off: usize,
+                                                         options: &QueryOptions)->bool{
+    let aabbs = parallel_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = parallel_build_bvh(&mut indices, &aabbs, cut_off);
+    parallel_self_broad_phase_any(&*bvh, &|i, j| {
+        shapes_intersect_with(&shapes[i], &poses[i], &shapes[j], &poses[j], options)
+    })
+}
+
+pub fn serial_any_collision_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                       poses: &[LieGroupISE3q],
+                                                       cut_off: usize,
+                                                       options: &QueryOptions)->bool{
+    let aabbs = serial_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = serial_build_bvh(&mut indices, &aabbs, cut_off);
+    serial_self_broad_phase_any(&*bvh, &|i, j| {
+        shapes_intersect_with(&shapes[i], &poses[i], &shapes[j], &poses[j], options)
+    })
+}
+
+// collision check that skips every pair rejected by filter (groups/masks, then the predicate).
+// rejected pairs are dropped inside the BVH traversal and never reach the narrow phase.
+pub fn parallel_filtered_collision_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                               poses: &[LieGroupISE3q],
+                                                               filter: &PairFilter,
+                                                               cut_off: usize)->Vec<Contact>{
+    parallel_filtered_collision_check_with(shapes, poses, filter, cut_off, &QueryOptions::default())
+}
+
+pub fn serial_filtered_collision_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                             poses: &[LieGroupISE3q],
+                                                             filter: &PairFilter,
+                                                             cut_off: usize)->Vec<Contact>{
+    serial_filtered_collision_check_with(shapes, poses, filter, cut_off, &QueryOptions::default())
+}
+
+pub fn parallel_filtered_collision_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                    poses: &[LieGroupISE3q],
+                                                                    filter: &PairFilter,
+                                                                    cut_off: usize,
+                                                                    options: &QueryOptions)->Vec<Contact>{
+    assert_eq!(shapes.len(), filter.len(), "every shape needs a collision filter");
+    let aabbs = parallel_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = parallel_build_bvh(&mut indices, &aabbs, cut_off);
+    let pairs = parallel_self_broad_phase_check_filtered(&*bvh, &|i, j| filter.accepts(i, j));
+    parallel_narrow_phase_check_with(&pairs, shapes, poses, options)
+}
+
+pub fn serial_filtered_collision_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                  poses: &[LieGroupISE3q],
+                                                                  filter: &PairFilter,
+                                                                  cut_off: usize,
+                                                                  options: &QueryOptions)->Vec<Contact>{
+    assert_eq!(shapes.len(), filter.len(), "every shape needs a collision filter");
+    let aabbs = serial_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = serial_build_bvh(&mut indices, &aabbs, cut_off);
+    let pairs = serial_self_broad_phase_check_filtered(&*bvh, &|i, j| filter.accepts(i, j));
+    serial_narrow_phase_check_with(&pairs, shapes, poses, options)
+}
+
+pub fn parallel_filtered_any_collision<S: ShapeTrait + Sync>(shapes: &[S],
+                                                             poses: &[LieGroupISE3q],
+                                                             filter: &PairFilter,
+                                                             cut_off: usize)->bool{
+    parallel_filtered_any_collision_with(shapes, poses, filter, cut_off, &QueryOptions::default())
+}
+
+pub fn serial_filtered_any_collision<S: ShapeTrait + Sync>(shapes: &[S],
+                                                           poses: &[LieGroupISE3q],
+                                                           filter: &PairFilter,
+                                                           cut_off: usize)->bool{
+    serial_filtered_any_collision_with(shapes, poses, filter, cut_off, &QueryOptions::default())
+}
+
+pub fn parallel_filtered_any_collision_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                  poses: &[LieGroupISE3q],
+                                                                  filter: &PairFilter,
+                                                                  cut_off: usize,
+                                                                  options: &QueryOptions)->bool{
+    assert_eq!(shapes.len(), filter.len(), "every shape needs a collision filter");
+    let aabbs = parallel_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = parallel_build_bvh(&mut indices, &aabbs, cut_off);
+    parallel_self_broad_phase_any(&*bvh, &|i, j| {
+        filter.accepts(i, j) && shapes_intersect_with(&shapes[i], &poses[i], &shapes[j], &poses[j], options)
+    })
+}
+
+pub fn serial_filtered_any_collision_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                poses: &[LieGroupISE3q],
+                                                                filter: &PairFilter,
+                                                                cut_off: usize,
+                                                                options: &QueryOptions)->bool{
+    assert_eq!(shapes.len(), filter.len(), "every shape needs a collision filter");
+    let aabbs = serial_build_aabbs_with(shapes, poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = serial_build_bvh(&mut indices, &aabbs, cut_off);
+    serial_self_broad_phase_any(&*bvh, &|i, j| {
+        filter.accepts(i, j) && shapes_intersect_with(&shapes[i], &poses[i], &shapes[j], &poses[j], options)
+    })
+}
+
+// BVH over a shape set at the given poses, to be reused across bipartite queries while the set
+// does not move. for a set that moves, keep a DynamicBVH (new_incremental_bvh) and pass its root.
+pub fn new_bvh<S: ShapeTrait + Sync>(shapes: &[S],
+                                     poses: &[LieGroupISE3q],
+                                     cut_off: usize)->Box<dyn BVHNode>{
+    let aabbs = parallel_build_aabbs(shapes, poses);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    parallel_build_bvh(&mut indices, &aabbs, cut_off)
+}
+
+// only contacts between the two sets (e.g. robot vs environment), pairs within a set are never
+// considered. in every contact i indexes shapes1 and j indexes shapes2.
+pub fn parallel_bipartite_collision_check<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(shapes1: &[S1],
+                                                                                         poses1: &[LieGroupISE3q],
+                                                                                         shapes2: &[S2],
+                                                                                         poses2: &[LieGroupISE3q],
+                                                                                         cut_off: usize)->Vec<Contact>{
+    parallel_bipartite_collision_check_with(shapes1, poses1, shapes2, poses2, cut_off, &QueryOptions::default())
+}
+
+pub fn parallel_bipartite_collision_check_with<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(shapes1: &[S1],
+                                                                                              poses1: &[LieGroupISE3q],
+                                                                                              shapes2: &[S2],
+                                                                                              poses2: &[LieGroupISE3q],
+                                                                                              cut_off: usize,
+                                                                                              options: &QueryOptions)->Vec<Contact>{
+    let bvh2 = new_bvh(shapes2, poses2, cut_off);
+    parallel_bipartite_collision_check_with_bvh(shapes1, poses1, &*bvh2, shapes2, poses2, cut_off, options)
+}
+
+// same as parallel_bipartite_collision_check_with, but the second set comes with a prebuilt BVH
+// (new_bvh, or DynamicBVH::root for a set that moves) and only the first one is rebuilt. the boxes
+// of bvh2 are taken as they are, the first set's boxes are grown by the whole contact threshold.
+pub fn parallel_bipartite_collision_check_with_bvh<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(shapes1: &[S1],
+                                                                                                  poses1: &[LieGroupISE3q],
+                                                                                                  bvh2: &dyn BVHNode,
+                                                                                                  shapes2: &[S2],
+                                                                                                  poses2: &[LieGroupISE3q],
+                                                                                                  cut_off: usize,
+                                                                                                  options: &QueryOptions)->Vec<Contact>{
+    let aabbs1 = parallel_build_aabbs_with(shapes1, poses1, options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs1.len()).collect();
+    let bvh1 = parallel_build_bvh(&mut indices, &aabbs1, cut_off);
+    let pairs = parallel_bipartite_broad_phase_check(&*bvh1, bvh2);
+    parallel_bipartite_narrow_phase_check_with(&pairs, shapes1, poses1, shapes2, poses2, options)
+}
+
+pub fn serial_bipartite_collision_check<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(shapes1: &[S1],
+                                                                                       poses1: &[LieGroupISE3q],
+                                                                                       shapes2: &[S2],
+                                                                                       poses2: &[LieGroupISE3q],
+                                                                                       cut_off: usize)->Vec<Contact>{
+    serial_bipartite_collision_check_with(shapes1, poses1, shapes2, poses2, cut_off, &QueryOptions::default())
+}
+
+pub fn serial_bipartite_collision_check_with<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(shapes1: &[S1],
+                                                                                            poses1: &[LieGroupISE3q],
+                                                                                            shapes2: &[S2],
+                                                                                            poses2: &[LieGroupISE3q],
+                                                                                            cut_off: usize,
+                                                                                            options: &QueryOptions)->Vec<Contact>{
+    let aabbs2 = serial_build_aabbs(shapes2, poses2);
+    let mut indices: Vec<usize> = (0..aabbs2.len()).collect();
+    let bvh2 = serial_build_bvh(&mut indices, &aabbs2, cut_off);
+    serial_bipartite_collision_check_with_bvh(shapes1, poses1, &*bvh2, shapes2, poses2, cut_off, options)
+}
+
+pub fn serial_bipartite_collision_check_with_bvh<S1: ShapeTrait + Sync, S2: ShapeTrait + Sync>(shapes1: &[S1],
+                                                                                                poses1: &[LieGroupISE3q],
+                                                                                                bvh2: &dyn BVHNode,
+                                                                                                shapes2: &[S2],
+                                                                                                poses2: &[LieGroupISE3q],
+                                                                                                cut_off: usize,
+                                                                                                options: &QueryOptions)->Vec<Contact>{
+    let aabbs1 = serial_build_aabbs_with(shapes1, poses1, options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs1.len()).collect();
+    let bvh1 = serial_build_bvh(&mut indices, &aabbs1, cut_off);
+    let pairs = serial_bipartite_broad_phase_check(&*bvh1, bvh2);
+    serial_bipartite_narrow_phase_check_with(&pairs, shapes1, poses1, shapes2, poses2, options)
+}
+
+// collision checking over a motion segment: every object moves from start_poses[k] to end_poses[k]
+// (linear translation, shortest-arc rotation), the broad phase runs on swept boxes and every pair that
+// touches at some time in [0, 1] is reported with its earliest time of impact
+pub fn parallel_swept_collision_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                            start_poses: &[LieGroupISE3q],
+                                                            end_poses: &[LieGroupISE3q],
+                                                            cut_off: usize)->Vec<TimeOfImpact>{
+    parallel_swept_collision_check_with(shapes, start_poses, end_poses, cut_off, &QueryOptions::default())
+}
+
+pub fn serial_swept_collision_check<S: ShapeTrait + Sync>(shapes: &[S],
+                                                          start_poses: &[LieGroupISE3q],
+                                                          end_poses: &[LieGroupISE3q],
+                                                          cut_off: usize)->Vec<TimeOfImpact>{
+    serial_swept_collision_check_with(shapes, start_poses, end_poses, cut_off, &QueryOptions::default())
+}
+
+// pairs coming within options.contact_threshold of each other count as touching
+pub fn parallel_swept_collision_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                                 start_poses: &[LieGroupISE3q],
+                                                                 end_poses: &[LieGroupISE3q],
+                                                                 cut_off: usize,
+                                                                 options: &QueryOptions)->Vec<TimeOfImpact>{
+    let aabbs = parallel_build_swept_aabbs_with(shapes, start_poses, end_poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = parallel_build_bvh(&mut indices, &aabbs, cut_off);
+    let pairs = parallel_self_broad_phase_check(&*bvh);
+    parallel_narrow_phase_time_of_impact_with(&pairs, shapes, start_poses, end_poses, options)
+}
+
+pub fn serial_swept_collision_check_with<S: ShapeTrait + Sync>(shapes: &[S],
+                                                               start_poses: &[LieGroupISE3q],
+                                                               end_poses: &[LieGroupISE3q],
+                                                               cut_off: usize,
+                                                               options: &QueryOptions)->Vec<TimeOfImpact>{
+    let aabbs = serial_build_swept_aabbs_with(shapes, start_poses, end_poses, 0.5*options.contact_threshold);
+    let mut indices: Vec<usize> = (0..aabbs.len()).collect();
+    let bvh = serial_build_bvh(&mut indices, &aabbs, cut_off);
+    let pairs = serial_self_broad_phase_check(&*bvh);
+    serial_narrow_phase_time_of_impact_with(&pairs, shapes, start_poses, end_poses, options)
+}
+
+// boolean baseline: overlapping pairs only, at distance 0 and without witness points or depth
 pub fn serial_parry_gjk(pairs: &[(usize, usize)], hulls: &[ParryConvexHull], poses: &[LieGroupISE3q])->Vec<Contact>{
         pairs.iter().filter_map(
             |&(i, j)
             | {
                 let dist = parry_distance(&poses[i].0, &hulls[i], &poses[j].0, &hulls[j]).unwrap();
-                (dist == 0.0).then(|| Contact { i, j})
+                (dist == 0.0).then(|| Contact { i, j, p1: V3::zeros(), p2: V3::zeros(), distance: 0.0, normal: V3::zeros(), parts: None, converged: true})
             }).collect()
 }
 
@@ -125,7 +519,26 @@ pub fn parallel_parry_gjk(pairs: &[(usize, usize)], hulls: &[ParryConvexHull], p
         |&(i, j)
         | {
             let dist = parry_distance(&poses[i].0, &hulls[i], &poses[j].0, &hulls[j]).unwrap();
-            (dist ==0.0).then(|| Contact { i, j})
+            (dist == 0.0).then(|| Contact { i, j, p1: V3::zeros(), p2: V3::zeros(), distance: 0.0, normal: V3::zeros(), parts: None, converged: true})
+        }).collect()
+}
+
+// penetration baseline: overlapping pairs with parry's depth, normal and witness points
+pub fn serial_parry_contact(pairs: &[(usize, usize)], hulls: &[ParryConvexHull], poses: &[LieGroupISE3q])->Vec<Contact>{
+        pairs.iter().filter_map(
+            |&(i, j)
+            | {
+                parry_contact(&poses[i].0, &hulls[i], &poses[j].0, &hulls[j], 0.0).unwrap()
+                    .map(|c| Contact { i, j, p1: c.point1.coords, p2: c.point2.coords, distance: c.dist, normal: c.normal1.into_inner(), parts: None, converged: true})
+            }).collect()
+}
+
+pub fn parallel_parry_contact(pairs: &[(usize, usize)], hulls: &[ParryConvexHull], poses: &[LieGroupISE3q])->Vec<Contact>{
+    pairs.par_iter().filter_map(
+        |&(i, j)
+        | {
+            parry_contact(&poses[i].0, &hulls[i], &poses[j].0, &hulls[j], 0.0).unwrap()
+                .map(|c| Contact { i, j, p1: c.point1.coords, p2: c.point2.coords, distance: c.dist, normal: c.normal1.into_inner(), parts: None, converged: true})
         }).collect()
 }
 
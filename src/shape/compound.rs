@@ -0,0 +1,161 @@
+use apollo_rust_lie::LieGroupElement;
+use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
+use apollo_rust_spatial::vectors::V3;
+use crate::bvh::srl_bvh::serial_build_bvh;
+use crate::bvh::structs::{AABB, BVHNode};
+use crate::gjk::gjk::{Contact, QueryOptions};
+use crate::shape::shape::{ConvexPolyhedron, ShapeTrait};
+
+const COMPOUND_CUT_OFF: usize = 2;
+
+// a rigid object made of several convex parts, each placed relative to the compound frame.
+// the local BVH over the part AABBs in the compound frame is built from the parts and offsets,
+// which are only changed through the setters below so that it is rebuilt with them.
+pub struct Compound {
+    parts: Vec<ConvexPolyhedron>,
+    offsets: Vec<LieGroupISE3q>, // pose of every part in the compound frame
+    local_aabbs: Vec<AABB>,
+    bvh: Box<dyn BVHNode>,
+}
+
+impl Compound {
+    pub fn new(parts: Vec<ConvexPolyhedron>, offsets: Vec<LieGroupISE3q>) -> Self {
+        assert_eq!(parts.len(), offsets.len(),
+                   "parts and offsets must have the same length");
+        assert!(!parts.is_empty(), "a compound needs at least one part");
+        let (local_aabbs, bvh) = part_bvh(&parts, &offsets);
+        Self { parts, offsets, local_aabbs, bvh }
+    }
+
+    pub fn parts(&self) -> &[ConvexPolyhedron] {
+        &self.parts
+    }
+
+    pub fn offsets(&self) -> &[LieGroupISE3q] {
+        &self.offsets
+    }
+
+    pub fn local_aabbs(&self) -> &[AABB] {
+        &self.local_aabbs
+    }
+
+    pub fn set_part(&mut self, k: usize, part: ConvexPolyhedron) {
+        self.parts[k] = part;
+        (self.local_aabbs, self.bvh) = part_bvh(&self.parts, &self.offsets);
+    }
+
+    pub fn set_offset(&mut self, k: usize, offset: LieGroupISE3q) {
+        self.offsets[k] = offset;
+        (self.local_aabbs, self.bvh) = part_bvh(&self.parts, &self.offsets);
+    }
+
+    pub fn part_pose(&self, k: usize, shape_pose: &LieGroupISE3q) -> LieGroupISE3q {
+        shape_pose.group_operator(&self.offsets[k])
+    }
+
+    // contact between this compound (as shape i) and any other shape (as shape j).
+    // the geometric fields come from the closest part pair, `parts` lists every touching one.
+    // it is only converged if every part query that was run converged.
+    pub fn contact_with<S: ShapeTrait>(&self, i: usize, j: usize, shape_pose: &LieGroupISE3q, other: &S, other_pose: &LieGroupISE3q, options: &QueryOptions) -> Contact {
+        let (min, max) = other.aabb(other_pose);
+        let query = aabb_in_frame(&AABB::new(min, max), shape_pose);
+        let mut best: Option<Contact> = None;
+        let mut touching: Vec<(usize, usize)> = Vec::new();
+        let mut converged = true;
+        self.nearest_parts(&*self.bvh, &query, i, j, shape_pose, other, other_pose, &mut best, &mut touching, &mut converged, options);
+        let mut best = best.unwrap();
+        best.parts = Some(touching);
+        best.converged = converged;
+        best
+    }
+
+    // branch and bound over the local BVH, a node can be skipped once its box is farther than
+    // the best distance so far (and than the contact threshold, so that every touching part is still collected)
+    #[allow(clippy::too_many_arguments)]
+    fn nearest_parts<S: ShapeTrait>(&self, node: &dyn BVHNode, query: &AABB, i: usize, j: usize,
+                                    shape_pose: &LieGroupISE3q, other: &S, other_pose: &LieGroupISE3q,
+                                    best: &mut Option<Contact>, touching: &mut Vec<(usize, usize)>, converged: &mut bool, options: &QueryOptions) {
+        let bound = best.as_ref().map_or(f64::INFINITY, |c| c.distance.max(options.contact_threshold));
+        if node.aabb_ref().distance(query) > bound {
+            return;
+        }
+        if node.is_leaf() {
+            for &k in node.leaf_indices().unwrap() {
+                let c = Contact::with_options(i, j, &self.parts[k], &self.part_pose(k, shape_pose), other, other_pose, options);
+                match &c.parts {
+                    Some(parts) => touching.extend(parts.iter().map(|&(_, l)| (k, l))),
+                    // a convex other shape is its own part 0
+                    None if c.distance <= options.contact_threshold => touching.push((k, 0)),
+                    None => {}
+                }
+                *converged &= c.converged;
+                if best.as_ref().is_none_or(|b| c.distance < b.distance) {
+                    *best = Some(c);
+                }
+            }
+            return;
+        }
+        let (l, r) = node.children();
+        let (l, r) = (l.unwrap(), r.unwrap());
+        // visit the nearer child first so the bound tightens early
+        let (first, second) = if l.aabb_ref().distance(query) <= r.aabb_ref().distance(query) { (l, r) } else { (r, l) };
+        self.nearest_parts(first, query, i, j, shape_pose, other, other_pose, best, touching, converged, options);
+        self.nearest_parts(second, query, i, j, shape_pose, other, other_pose, best, touching, converged, options);
+    }
+}
+
+// part AABBs in the compound frame and the BVH over them
+fn part_bvh(parts: &[ConvexPolyhedron], offsets: &[LieGroupISE3q]) -> (Vec<AABB>, Box<dyn BVHNode>) {
+    let local_aabbs: Vec<AABB> = parts.iter().zip(offsets.iter())
+        .map(|(part, offset)| { let (min, max) = part.aabb(offset); AABB::new(min, max) })
+        .collect();
+    let mut indices: Vec<usize> = (0..parts.len()).collect();
+    let bvh = serial_build_bvh(&mut indices, &local_aabbs, COMPOUND_CUT_OFF);
+    (local_aabbs, bvh)
+}
+
+// box enclosing a world-frame AABB, expressed in the frame given by pose
+fn aabb_in_frame(aabb: &AABB, pose: &LieGroupISE3q) -> AABB {
+    let inv_rot = pose.0.rotation.inverse();
+    let t = pose.0.translation.vector;
+    let mut min_v = V3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max_v = V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for corner in 0..8 {
+        let p = V3::new(
+            if corner & 1 == 0 { aabb.min_coords.x } else { aabb.max_coords.x },
+            if corner & 2 == 0 { aabb.min_coords.y } else { aabb.max_coords.y },
+            if corner & 4 == 0 { aabb.min_coords.z } else { aabb.max_coords.z },
+        );
+        let local = inv_rot * (p - t);
+        min_v = min_v.inf(&local);
+        max_v = max_v.sup(&local);
+    }
+    AABB::new(min_v, max_v)
+}
+
+impl ShapeTrait for Compound {
+    // support of the convex hull of all parts, only meaningful as a bound. the GJK queries
+    // (Contact, gjk_contact, gjk_penetration, shapes_intersect) check for as_compound and go
+    // through the parts instead.
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        (0..self.parts.len())
+            .map(|k| self.parts[k].support(dir, &self.part_pose(k, shape_pose)))
+            .max_by(|a, b| a.dot(dir).total_cmp(&b.dot(dir)))
+            .unwrap()
+    }
+
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        let mut min_v = V3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max_v = V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for k in 0..self.parts.len() {
+            let (min, max) = self.parts[k].aabb(&self.part_pose(k, shape_pose));
+            min_v = min_v.inf(&min);
+            max_v = max_v.sup(&max);
+        }
+        (min_v, max_v)
+    }
+
+    fn as_compound(&self) -> Option<&Compound> {
+        Some(self)
+    }
+}
@@ -0,0 +1,81 @@
+use apollo_rust_lie::LieGroupElement;
+use apollo_rust_mesh_utils::trimesh::TriMesh;
+use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
+use apollo_rust_spatial::vectors::V3;
+use parry3d_f64::math::Point;
+use parry3d_f64::transformation::vhacd::{VHACD, VHACDParameters};
+use crate::shape::compound::Compound;
+use crate::shape::shape::ConvexPolyhedron;
+
+// hulls thinner than this, relative to their size, enclose no volume and are dropped
+const FLAT_HULL_TOL: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy)]
+pub struct DecompositionParameters {
+    pub concavity: f64, // maximum concavity allowed per part, relative to the mesh size
+    pub resolution: u32, // voxel grid resolution along the longest axis
+    pub max_convex_hulls: u32,
+}
+
+impl Default for DecompositionParameters {
+    fn default() -> Self {
+        Self { concavity: 0.01, resolution: 64, max_convex_hulls: 1024 }
+    }
+}
+
+// approximate convex decomposition (V-HACD) of a possibly concave triangle mesh.
+// each returned hull is expressed in the frame of the input mesh. hulls with no volume are skipped,
+// so the result is empty for a flat or very thin mesh.
+pub fn convex_decomposition(mesh: &TriMesh, params: &DecompositionParameters) -> Vec<ConvexPolyhedron> {
+    let points: Vec<Point<f64>> = mesh.points.iter().map(|p| Point::new(p[0], p[1], p[2])).collect();
+    let indices: Vec<[u32; 3]> = mesh.indices.iter().map(|f| [f[0] as u32, f[1] as u32, f[2] as u32]).collect();
+    let vhacd_params = VHACDParameters {
+        concavity: params.concavity,
+        resolution: params.resolution,
+        max_convex_hulls: params.max_convex_hulls,
+        ..VHACDParameters::default()
+    };
+    // keep the voxel to triangle map so the hulls are built from the original vertices
+    let decomposition = VHACD::decompose(&vhacd_params, &points, &indices, true);
+    decomposition.compute_exact_convex_hulls(&points, &indices)
+        .iter()
+        .map(|(hull_points, _)| hull_points.iter().map(|p| p.coords).collect::<Vec<V3>>())
+        .filter(|pts| spans_volume(pts))
+        .map(|pts| ConvexPolyhedron::from_points(&pts))
+        .collect()
+}
+
+// whether the points are not all (up to FLAT_HULL_TOL) on one plane: take the point farthest from
+// the first one, then the one farthest from their line, and look for a point off their plane
+fn spans_volume(points: &[V3]) -> bool {
+    let Some(&a) = points.first() else { return false };
+    let (size, b) = farthest(points, |p| (p - a).norm());
+    if size == 0.0 {
+        return false;
+    }
+    let axis = (b - a) / size;
+    let (offset, c) = farthest(points, |p| (p - a).cross(&axis).norm());
+    if offset <= FLAT_HULL_TOL * size {
+        return false;
+    }
+    let normal = (c - a).cross(&axis).normalize();
+    farthest(points, |p| (p - a).dot(&normal).abs()).0 > FLAT_HULL_TOL * size
+}
+
+fn farthest<F: Fn(&V3) -> f64>(points: &[V3], dist: F) -> (f64, V3) {
+    points.iter().map(|p| (dist(p), *p)).max_by(|x, y| x.0.total_cmp(&y.0)).unwrap()
+}
+
+impl Compound {
+    // a compound whose parts are the convex decomposition of mesh, all at identity offset.
+    // a mesh that decomposes into no solid part (a plate, a sheet) keeps its single convex hull.
+    pub fn from_trimesh(mesh: &TriMesh, params: &DecompositionParameters) -> Self {
+        assert!(!mesh.points.is_empty(), "cannot decompose a mesh without points");
+        let mut parts = convex_decomposition(mesh, params);
+        if parts.is_empty() {
+            parts.push(ConvexPolyhedron::new(mesh));
+        }
+        let offsets = vec![LieGroupISE3q::identity_element(); parts.len()];
+        Compound::new(parts, offsets)
+    }
+}
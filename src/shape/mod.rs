@@ -1,2 +1,5 @@
+#[allow(clippy::module_inception)]
 pub mod shape;
-
+pub mod primitives;
+pub mod compound;
+pub mod decomposition;
@@ -0,0 +1,196 @@
+use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
+use apollo_rust_spatial::vectors::V3;
+use crate::shape::shape::ShapeTrait;
+
+// analytic convex primitives, all centered at the origin of their local frame.
+// capsule, cylinder and cone have their axis along local z.
+
+#[derive(Debug, Clone, Copy)]
+pub struct Sphere {
+    pub radius: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Cuboid {
+    pub half_extents: V3,
+}
+
+// segment from (0, 0, -half_height) to (0, 0, half_height) swept by a sphere
+#[derive(Debug, Clone, Copy)]
+pub struct Capsule {
+    pub half_height: f64,
+    pub radius: f64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Cylinder {
+    pub half_height: f64,
+    pub radius: f64,
+}
+
+// apex at (0, 0, half_height), base disk centered at (0, 0, -half_height)
+#[derive(Debug, Clone, Copy)]
+pub struct Cone {
+    pub half_height: f64,
+    pub radius: f64,
+}
+
+// a heterogeneous collection of primitives goes through this enum
+#[derive(Debug, Clone, Copy)]
+pub enum Primitive {
+    Sphere(Sphere),
+    Cuboid(Cuboid),
+    Capsule(Capsule),
+    Cylinder(Cylinder),
+    Cone(Cone),
+}
+
+impl Sphere {
+    pub fn new(radius: f64) -> Self {
+        Self { radius }
+    }
+}
+
+impl Cuboid {
+    pub fn new(half_extents: V3) -> Self {
+        Self { half_extents }
+    }
+}
+
+impl Capsule {
+    pub fn new(half_height: f64, radius: f64) -> Self {
+        Self { half_height, radius }
+    }
+}
+
+impl Cylinder {
+    pub fn new(half_height: f64, radius: f64) -> Self {
+        Self { half_height, radius }
+    }
+}
+
+impl Cone {
+    pub fn new(half_height: f64, radius: f64) -> Self {
+        Self { half_height, radius }
+    }
+}
+
+fn sign(x: f64) -> f64 {
+    if x >= 0.0 { 1.0 } else { -1.0 }
+}
+
+fn to_world(local_point: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+    shape_pose.0.rotation * local_point + shape_pose.0.translation.vector
+}
+
+// per-axis half extent of a disk of the given radius whose normal is the unit vector axis
+fn disk_extent(axis: &V3, radius: f64) -> V3 {
+    V3::new(
+        radius * (1.0 - axis.x * axis.x).max(0.0).sqrt(),
+        radius * (1.0 - axis.y * axis.y).max(0.0).sqrt(),
+        radius * (1.0 - axis.z * axis.z).max(0.0).sqrt(),
+    )
+}
+
+// unit vector of the xy part of a local direction, zero if the direction is parallel to z
+fn radial(local_dir: &V3) -> V3 {
+    let n = (local_dir.x * local_dir.x + local_dir.y * local_dir.y).sqrt();
+    if n > 1e-12 { V3::new(local_dir.x / n, local_dir.y / n, 0.0) } else { V3::zeros() }
+}
+
+impl ShapeTrait for Sphere {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        let n = dir.norm();
+        let offset = if n > 1e-12 { dir.scale(self.radius / n) } else { V3::zeros() };
+        shape_pose.0.translation.vector + offset
+    }
+
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        let c = shape_pose.0.translation.vector;
+        (c.add_scalar(-self.radius), c.add_scalar(self.radius))
+    }
+}
+
+impl ShapeTrait for Cuboid {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        let local_dir = shape_pose.0.rotation.inverse() * dir;
+        let h = &self.half_extents;
+        to_world(&V3::new(sign(local_dir.x) * h.x, sign(local_dir.y) * h.y, sign(local_dir.z) * h.z), shape_pose)
+    }
+
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        let c = shape_pose.0.translation.vector;
+        let extent = shape_pose.0.rotation.to_rotation_matrix().matrix().abs() * self.half_extents;
+        (c - extent, c + extent)
+    }
+}
+
+impl ShapeTrait for Capsule {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        let local_dir = shape_pose.0.rotation.inverse() * dir;
+        let n = local_dir.norm();
+        let rounded = if n > 1e-12 { local_dir.scale(self.radius / n) } else { V3::zeros() };
+        to_world(&(V3::new(0.0, 0.0, sign(local_dir.z) * self.half_height) + rounded), shape_pose)
+    }
+
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        let a = to_world(&V3::new(0.0, 0.0, -self.half_height), shape_pose);
+        let b = to_world(&V3::new(0.0, 0.0, self.half_height), shape_pose);
+        (a.inf(&b).add_scalar(-self.radius), a.sup(&b).add_scalar(self.radius))
+    }
+}
+
+impl ShapeTrait for Cylinder {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        let local_dir = shape_pose.0.rotation.inverse() * dir;
+        let rim = radial(&local_dir).scale(self.radius);
+        to_world(&V3::new(rim.x, rim.y, sign(local_dir.z) * self.half_height), shape_pose)
+    }
+
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        let c = shape_pose.0.translation.vector;
+        let axis = shape_pose.0.rotation * V3::z();
+        let extent = axis.abs().scale(self.half_height) + disk_extent(&axis, self.radius);
+        (c - extent, c + extent)
+    }
+}
+
+impl ShapeTrait for Cone {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        let local_dir = shape_pose.0.rotation.inverse() * dir;
+        let apex = V3::new(0.0, 0.0, self.half_height);
+        let rim = radial(&local_dir).scale(self.radius) - apex;
+        let best = if apex.dot(&local_dir) >= rim.dot(&local_dir) { apex } else { rim };
+        to_world(&best, shape_pose)
+    }
+
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        let axis = shape_pose.0.rotation * V3::z();
+        let apex = to_world(&V3::new(0.0, 0.0, self.half_height), shape_pose);
+        let base = to_world(&V3::new(0.0, 0.0, -self.half_height), shape_pose);
+        let extent = disk_extent(&axis, self.radius);
+        ((base - extent).inf(&apex), (base + extent).sup(&apex))
+    }
+}
+
+impl ShapeTrait for Primitive {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        match self {
+            Primitive::Sphere(s) => s.support(dir, shape_pose),
+            Primitive::Cuboid(s) => s.support(dir, shape_pose),
+            Primitive::Capsule(s) => s.support(dir, shape_pose),
+            Primitive::Cylinder(s) => s.support(dir, shape_pose),
+            Primitive::Cone(s) => s.support(dir, shape_pose),
+        }
+    }
+
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        match self {
+            Primitive::Sphere(s) => s.aabb(shape_pose),
+            Primitive::Cuboid(s) => s.aabb(shape_pose),
+            Primitive::Capsule(s) => s.aabb(shape_pose),
+            Primitive::Cylinder(s) => s.aabb(shape_pose),
+            Primitive::Cone(s) => s.aabb(shape_pose),
+        }
+    }
+}
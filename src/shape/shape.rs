@@ -1,53 +1,196 @@
-use std::ops::Mul;
-use apollo_rust_lie::{EuclideanSpaceElement, LieGroupElement};
 use apollo_rust_mesh_utils::trimesh::TriMesh;
 use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
-use apollo_rust_spatial::vectors::{ApolloVector3Trait, V3};
+use apollo_rust_spatial::vectors::V3;
 use parry3d_f64::math::Point;
 use parry3d_f64::transformation::convex_hull;
+use std::sync::Arc;
+use crate::shape::compound::Compound;
+use crate::shape::primitives::{Capsule, Cone, Cuboid, Cylinder, Primitive, Sphere};
 
 pub trait ShapeTrait {
     fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3;
+    // support with a per-query hint: vertex is where the previous query of the same caller ended
+    // (usize::MAX for none) and is updated to where this one ends. only large hulls make use of it.
+    fn support_from(&self, dir: &V3, shape_pose: &LieGroupISE3q, _vertex: &mut usize) -> V3 {
+        self.support(dir, shape_pose)
+    }
     fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3);
+    // non-convex shapes made of convex parts are dispatched per part in the narrow phase
+    fn as_compound(&self) -> Option<&Compound> { None }
 }
 
-pub struct ConvexPolyhedron(pub TriMesh);
+// trait objects and shared references can be mixed freely in the pipelines, e.g. &[Box<dyn ShapeTrait + Send + Sync>]
+impl<T: ShapeTrait + ?Sized> ShapeTrait for Box<T> {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        (**self).support(dir, shape_pose)
+    }
+    fn support_from(&self, dir: &V3, shape_pose: &LieGroupISE3q, vertex: &mut usize) -> V3 {
+        (**self).support_from(dir, shape_pose, vertex)
+    }
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        (**self).aabb(shape_pose)
+    }
+    fn as_compound(&self) -> Option<&Compound> {
+        (**self).as_compound()
+    }
+}
+
+impl<T: ShapeTrait + ?Sized> ShapeTrait for Arc<T> {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        (**self).support(dir, shape_pose)
+    }
+    fn support_from(&self, dir: &V3, shape_pose: &LieGroupISE3q, vertex: &mut usize) -> V3 {
+        (**self).support_from(dir, shape_pose, vertex)
+    }
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        (**self).aabb(shape_pose)
+    }
+    fn as_compound(&self) -> Option<&Compound> {
+        (**self).as_compound()
+    }
+}
+
+impl<T: ShapeTrait + ?Sized> ShapeTrait for &T {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        (**self).support(dir, shape_pose)
+    }
+    fn support_from(&self, dir: &V3, shape_pose: &LieGroupISE3q, vertex: &mut usize) -> V3 {
+        (**self).support_from(dir, shape_pose, vertex)
+    }
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        (**self).aabb(shape_pose)
+    }
+    fn as_compound(&self) -> Option<&Compound> {
+        (**self).as_compound()
+    }
+}
+
+// below this many vertices a linear scan beats walking the adjacency graph
+const HILL_CLIMBING_MIN_VERTICES: usize = 32;
+
+// the hull mesh, plus its vertex adjacency for hill-climbing support queries on large hulls.
+// the mesh is only reachable through mesh() so that the two cannot get out of sync.
+pub struct ConvexPolyhedron {
+    mesh: TriMesh,
+    graph: Option<VertexGraph>,
+}
+
+// vertex adjacency of a hull mesh in compressed form: the neighbours of v are
+// neighbours[offsets[v]..offsets[v + 1]]. start is where queries without a hint begin.
+struct VertexGraph {
+    offsets: Vec<usize>,
+    neighbours: Vec<usize>,
+    start: usize,
+}
+
+impl VertexGraph {
+    fn new(mesh: &TriMesh) -> Option<Self> {
+        let first = mesh.indices.first()?[0];
+        let mut adjacency = vec![Vec::new(); mesh.points.len()];
+        for face in &mesh.indices {
+            for k in 0..3 {
+                let (a, b) = (face[k], face[(k + 1) % 3]);
+                adjacency[a].push(b);
+                adjacency[b].push(a);
+            }
+        }
+        let mut offsets = Vec::with_capacity(adjacency.len() + 1);
+        let mut neighbours = Vec::new();
+        offsets.push(0);
+        for mut adjacent in adjacency {
+            adjacent.sort_unstable();
+            adjacent.dedup();
+            neighbours.extend(adjacent);
+            offsets.push(neighbours.len());
+        }
+        Some(Self { offsets, neighbours, start: first })
+    }
+
+    // on a convex surface a vertex with no better neighbour is a global maximum, so climbing from
+    // any vertex of the mesh ends on the support point. starting from the vertex the caller's
+    // previous query ended on makes successive queries in nearby directions (GJK iterations)
+    // take only a few steps.
+    fn support(&self, points: &[[f64; 3]], local_dir: &V3, vertex: &mut usize) -> V3 {
+        let mut best = if *vertex < points.len() { *vertex } else { self.start };
+        let mut best_proj = V3::from_column_slice(&points[best]).dot(local_dir);
+        loop {
+            let current = best;
+            for &k in &self.neighbours[self.offsets[current]..self.offsets[current + 1]] {
+                let proj = V3::from_column_slice(&points[k]).dot(local_dir);
+                if proj > best_proj {
+                    best_proj = proj;
+                    best = k;
+                }
+            }
+            if best == current { break; }
+        }
+        *vertex = best;
+        V3::from_column_slice(&points[best])
+    }
+}
+
+// every shape kind the crate knows about, for scenes that mix them without boxing
+pub enum Shape {
+    ConvexPolyhedron(ConvexPolyhedron),
+    Primitive(Primitive),
+    Compound(Compound),
+}
 
 impl ConvexPolyhedron {
     pub fn new(input_mesh: &TriMesh)->Self{
-        Self(input_mesh.to_convex_hull())
+        Self::from_hull_mesh(input_mesh.to_convex_hull())
     }
     pub fn from_points(points: &[V3])->Self{
         let pts: Vec<_> = points.iter().map(|x| Point::from_slice(x.as_slice())).collect();
         let (ch_points, ch_indices) = convex_hull(&pts);
         let points: Vec<[f64; 3]> = ch_points.iter().map(|x| [x[0], x[1], x[2]] ).collect();
         let indices: Vec<[usize; 3]> = ch_indices.iter().map(|x| [ x[0] as usize, x[1] as usize, x[2] as usize]).collect();
-        Self(TriMesh {
+        Self::from_hull_mesh(TriMesh {
             points,
             indices
         })
     }
-}
-impl ShapeTrait for ConvexPolyhedron {
-    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
-        let local_dir = shape_pose.0.rotation.inverse() * dir;
-        let mut max_point = V3::from_column_slice(&self.0.points[0]);
-        let mut max_proj = max_point.dot(&local_dir);
-        for point in self.0.points.iter().skip(1) {
+    // mesh must already be convex, the adjacency graph is only built for large hulls
+    pub fn from_hull_mesh(mesh: TriMesh)->Self{
+        let graph = if mesh.points.len() >= HILL_CLIMBING_MIN_VERTICES { VertexGraph::new(&mesh) } else { None };
+        Self { mesh, graph }
+    }
+    pub fn mesh(&self) -> &TriMesh {
+        &self.mesh
+    }
+    fn linear_support(&self, local_dir: &V3) -> V3 {
+        let mut max_point = V3::from_column_slice(&self.mesh.points[0]);
+        let mut max_proj = max_point.dot(local_dir);
+        for point in self.mesh.points.iter().skip(1) {
             let cur_point = V3::from_column_slice(point);
-            let proj =cur_point.dot(&local_dir);
+            let proj =cur_point.dot(local_dir);
             if proj > max_proj {
                 max_proj = proj;
                 max_point = cur_point;
             }
         }
+        max_point
+    }
+}
+impl ShapeTrait for ConvexPolyhedron {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        let mut vertex = usize::MAX;
+        self.support_from(dir, shape_pose, &mut vertex)
+    }
+
+    fn support_from(&self, dir: &V3, shape_pose: &LieGroupISE3q, vertex: &mut usize) -> V3 {
+        let local_dir = shape_pose.0.rotation.inverse() * dir;
+        let max_point = match &self.graph {
+            Some(graph) => graph.support(&self.mesh.points, &local_dir, vertex),
+            None => self.linear_support(&local_dir),
+        };
         shape_pose.0.rotation*max_point+shape_pose.0.translation.vector
     }
 
     fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
        let mut min_v = V3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY);
        let mut max_v = V3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
-       for point in self.0.points.iter(){
+       for point in self.mesh.points.iter(){
 
            let cur_point = shape_pose.0.rotation*V3::from_column_slice(point)+shape_pose.0.translation.vector;
            min_v = min_v.inf(&cur_point);
@@ -57,4 +200,72 @@ impl ShapeTrait for ConvexPolyhedron {
     }
 }
 
+impl ShapeTrait for Shape {
+    fn support(&self, dir: &V3, shape_pose: &LieGroupISE3q) -> V3 {
+        match self {
+            Shape::ConvexPolyhedron(s) => s.support(dir, shape_pose),
+            Shape::Primitive(s) => s.support(dir, shape_pose),
+            Shape::Compound(s) => s.support(dir, shape_pose),
+        }
+    }
+
+    fn support_from(&self, dir: &V3, shape_pose: &LieGroupISE3q, vertex: &mut usize) -> V3 {
+        match self {
+            Shape::ConvexPolyhedron(s) => s.support_from(dir, shape_pose, vertex),
+            Shape::Primitive(s) => s.support(dir, shape_pose),
+            Shape::Compound(s) => s.support(dir, shape_pose),
+        }
+    }
+
+    fn aabb(&self, shape_pose: &LieGroupISE3q) -> (V3, V3) {
+        match self {
+            Shape::ConvexPolyhedron(s) => s.aabb(shape_pose),
+            Shape::Primitive(s) => s.aabb(shape_pose),
+            Shape::Compound(s) => s.aabb(shape_pose),
+        }
+    }
+
+    fn as_compound(&self) -> Option<&Compound> {
+        match self {
+            Shape::Compound(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+// the mesh must already be convex, same as from_hull_mesh
+impl From<TriMesh> for ConvexPolyhedron {
+    fn from(mesh: TriMesh) -> Self { ConvexPolyhedron::from_hull_mesh(mesh) }
+}
 
+impl From<ConvexPolyhedron> for Shape {
+    fn from(s: ConvexPolyhedron) -> Self { Shape::ConvexPolyhedron(s) }
+}
+
+impl From<Primitive> for Shape {
+    fn from(s: Primitive) -> Self { Shape::Primitive(s) }
+}
+
+impl From<Compound> for Shape {
+    fn from(s: Compound) -> Self { Shape::Compound(s) }
+}
+
+impl From<Sphere> for Shape {
+    fn from(s: Sphere) -> Self { Shape::Primitive(Primitive::Sphere(s)) }
+}
+
+impl From<Cuboid> for Shape {
+    fn from(s: Cuboid) -> Self { Shape::Primitive(Primitive::Cuboid(s)) }
+}
+
+impl From<Capsule> for Shape {
+    fn from(s: Capsule) -> Self { Shape::Primitive(Primitive::Capsule(s)) }
+}
+
+impl From<Cylinder> for Shape {
+    fn from(s: Cylinder) -> Self { Shape::Primitive(Primitive::Cylinder(s)) }
+}
+
+impl From<Cone> for Shape {
+    fn from(s: Cone) -> Self { Shape::Primitive(Primitive::Cone(s)) }
+}
@@ -0,0 +1,222 @@
+use apollo_rust_spatial::lie::se3_implicit_quaternion::LieGroupISE3q;
+use apollo_rust_spatial::vectors::V3;
+use rayon::prelude::*;
+use crate::bvh::dynamic::DynamicBVH;
+use crate::bvh::par_bvh::{parallel_build_bvh, parallel_self_broad_phase_any, parallel_self_broad_phase_check};
+use crate::bvh::structs::{AABB, BVHNode};
+use crate::gjk::gjk::{shapes_intersect_with, Contact, QueryOptions};
+use crate::shape::shape::{Shape, ShapeTrait};
+
+// stable reference to an object in a CollisionWorld, a removed object's handle is never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShapeHandle {
+    index: usize,
+    generation: u32,
+}
+
+impl ShapeHandle {
+    pub fn index(&self) -> usize {
+        self.index
+    }
+}
+
+struct WorldObject<S> {
+    shape: S,
+    pose: LieGroupISE3q,
+}
+
+// owns shapes and poses, hands out handles and keeps a refittable BVH in sync with them.
+// insert/remove rebuild the tree on the next query, set_pose only refits it.
+pub struct CollisionWorld<S: ShapeTrait + Send + Sync = Shape> {
+    slots: Vec<Option<WorldObject<S>>>,
+    generations: Vec<u32>,
+    free: Vec<usize>,
+    bvh: Option<DynamicBVH>,
+    structure_changed: bool,
+    poses_changed: bool,
+    cut_off: usize,
+    margin: f64, // boxes in the tree are grown by half of it, proximity queries up to it reuse the tree
+    options: QueryOptions, // used by every query, collisions report pairs within options.contact_threshold
+}
+
+impl<S: ShapeTrait + Send + Sync> CollisionWorld<S> {
+    pub fn new(cut_off: usize) -> Self {
+        Self::with_margin(cut_off, 0.0)
+    }
+
+    pub fn with_margin(cut_off: usize, margin: f64) -> Self {
+        Self {
+            slots: Vec::new(),
+            generations: Vec::new(),
+            free: Vec::new(),
+            bvh: None,
+            structure_changed: false,
+            poses_changed: false,
+            cut_off,
+            margin,
+            options: QueryOptions::default(),
+        }
+    }
+
+    pub fn options(&self) -> &QueryOptions {
+        &self.options
+    }
+
+    pub fn set_options(&mut self, options: QueryOptions) {
+        self.options = options;
+    }
+
+    pub fn insert(&mut self, shape: S, pose: LieGroupISE3q) -> ShapeHandle {
+        self.structure_changed = true;
+        let object = Some(WorldObject { shape, pose });
+        if let Some(index) = self.free.pop() {
+            self.slots[index] = object;
+            ShapeHandle { index, generation: self.generations[index] }
+        } else {
+            self.slots.push(object);
+            self.generations.push(0);
+            ShapeHandle { index: self.slots.len() - 1, generation: 0 }
+        }
+    }
+
+    pub fn remove(&mut self, handle: ShapeHandle) -> Option<S> {
+        if !self.contains(handle) {
+            return None;
+        }
+        self.structure_changed = true;
+        self.generations[handle.index] += 1;
+        self.free.push(handle.index);
+        self.slots[handle.index].take().map(|o| o.shape)
+    }
+
+    pub fn contains(&self, handle: ShapeHandle) -> bool {
+        handle.index < self.slots.len()
+            && self.generations[handle.index] == handle.generation
+            && self.slots[handle.index].is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn shape(&self, handle: ShapeHandle) -> Option<&S> {
+        self.object(handle).map(|o| &o.shape)
+    }
+
+    pub fn pose(&self, handle: ShapeHandle) -> Option<&LieGroupISE3q> {
+        self.object(handle).map(|o| &o.pose)
+    }
+
+    // returns false if the handle is stale
+    pub fn set_pose(&mut self, handle: ShapeHandle, pose: LieGroupISE3q) -> bool {
+        if !self.contains(handle) {
+            return false;
+        }
+        self.slots[handle.index].as_mut().unwrap().pose = pose;
+        self.poses_changed = true;
+        true
+    }
+
+    pub fn handles(&self) -> impl Iterator<Item = ShapeHandle> + '_ {
+        self.slots.iter().enumerate()
+            .filter(|(_, o)| o.is_some())
+            .map(|(index, _)| ShapeHandle { index, generation: self.generations[index] })
+    }
+
+    fn object(&self, handle: ShapeHandle) -> Option<&WorldObject<S>> {
+        if self.contains(handle) { self.slots[handle.index].as_ref() } else { None }
+    }
+
+    fn handle_of(&self, index: usize) -> ShapeHandle {
+        ShapeHandle { index, generation: self.generations[index] }
+    }
+
+    fn live_indices(&self) -> Vec<usize> {
+        (0..self.slots.len()).filter(|&i| self.slots[i].is_some()).collect()
+    }
+
+    // removed slots keep a dummy box, they are never part of the tree
+    fn current_aabbs(&self, margin: f64) -> Vec<AABB> {
+        self.slots.par_iter()
+            .map(|o| match o {
+                Some(o) => { let (min, max) = o.shape.aabb(&o.pose); AABB::new(min, max).inflated(0.5 * margin) },
+                None => AABB::new(V3::zeros(), V3::zeros()),
+            })
+            .collect()
+    }
+
+    // brings the BVH up to date with every insert/remove/set_pose since the last query
+    pub fn update(&mut self) {
+        if self.structure_changed || (self.bvh.is_none() && !self.is_empty()) {
+            let indices = self.live_indices();
+            self.bvh = (!indices.is_empty())
+                .then(|| DynamicBVH::from_indices(self.current_aabbs(self.margin), indices, self.cut_off));
+        } else if self.poses_changed {
+            let aabbs = self.current_aabbs(self.margin);
+            if let Some(bvh) = self.bvh.as_mut() {
+                bvh.set_aabbs(aabbs);
+                bvh.refit();
+            }
+        }
+        self.structure_changed = false;
+        self.poses_changed = false;
+    }
+
+    fn narrow_phase(&self, pairs: &[(usize, usize)], margin: f64) -> Vec<(ShapeHandle, ShapeHandle, Contact)> {
+        let options = self.options.with_margin(margin);
+        pairs.par_iter()
+            .filter_map(|&(i, j)| {
+                let (a, b) = (self.slots[i].as_ref().unwrap(), self.slots[j].as_ref().unwrap());
+                let c = Contact::with_options(i, j, &a.shape, &a.pose, &b.shape, &b.pose, &options);
+                c.is_within(margin).then(|| (self.handle_of(i), self.handle_of(j), c))
+            })
+            .collect()
+    }
+
+    pub fn collisions(&mut self) -> Vec<(ShapeHandle, ShapeHandle, Contact)> {
+        self.proximity(self.options.contact_threshold)
+    }
+
+    // every pair closer than margin, penetrating pairs included
+    pub fn proximity(&mut self, margin: f64) -> Vec<(ShapeHandle, ShapeHandle, Contact)> {
+        self.update();
+        let Some(bvh) = self.bvh.as_ref() else { return Vec::new() };
+        let pairs = if margin <= self.margin {
+            parallel_self_broad_phase_check(bvh.root())
+        } else {
+            parallel_self_broad_phase_check(&*self.throwaway_bvh(margin))
+        };
+        self.narrow_phase(&pairs, margin)
+    }
+
+    // the persistent tree is not inflated enough for margin, build one that is
+    fn throwaway_bvh(&self, margin: f64) -> Box<dyn BVHNode> {
+        let aabbs = self.current_aabbs(margin);
+        let mut indices = self.live_indices();
+        parallel_build_bvh(&mut indices, &aabbs, self.cut_off)
+    }
+
+    pub fn any_collision(&mut self) -> bool {
+        self.update();
+        let Some(bvh) = self.bvh.as_ref() else { return false };
+        let test = |i: usize, j: usize| {
+            let (a, b) = (self.slots[i].as_ref().unwrap(), self.slots[j].as_ref().unwrap());
+            shapes_intersect_with(&a.shape, &a.pose, &b.shape, &b.pose, &self.options)
+        };
+        if self.options.contact_threshold <= self.margin {
+            parallel_self_broad_phase_any(bvh.root(), &test)
+        } else {
+            parallel_self_broad_phase_any(&*self.throwaway_bvh(self.options.contact_threshold), &test)
+        }
+    }
+
+    // full contact information for one pair, penetration depth included, None if either handle is stale
+    pub fn distance(&self, h1: ShapeHandle, h2: ShapeHandle) -> Option<Contact> {
+        let (a, b) = (self.object(h1)?, self.object(h2)?);
+        Some(Contact::with_options(h1.index, h2.index, &a.shape, &a.pose, &b.shape, &b.pose, &self.options.with_penetration()))
+    }
+}